@@ -1,6 +1,11 @@
 use serde::Serialize;
-use crate::blockchain::Blockchain;
+use crate::blockchain::{Blockchain, Mempool, Receipt};
+use crate::types::address::Address;
+use crate::types::hash::{H256, Hashable};
+use crate::types::transaction::HtlcAction;
 use crate::miner::Handle as MinerHandle;
+use crate::miner::generate_blocks;
+use crate::miner::strategy::Strategy;
 use crate::txgen::Handle as GeneratorHandle;
 use crate::network::server::Handle as NetworkServerHandle;
 use crate::network::message::Message;
@@ -9,6 +14,7 @@ use log::info;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tiny_http::Header;
 use tiny_http::Response;
 use tiny_http::Server as HTTPServer;
@@ -20,6 +26,7 @@ pub struct Server {
     generator: GeneratorHandle,
     network: NetworkServerHandle,
     blockchain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
 }
 
 #[derive(Serialize)]
@@ -28,6 +35,122 @@ struct ApiResponse {
     message: String,
 }
 
+#[derive(Serialize)]
+struct MempoolTransaction {
+    hash: String,
+    sender: String,
+    outputs: Vec<(String, u32)>,
+    value: u32,
+    account_nonce: u32,
+    size: u64,
+    age_ms: u128,
+    memo: String,
+    lock_height: u64,
+    lock_time: u128,
+}
+
+#[derive(Serialize)]
+struct ChainTransaction {
+    hash: String,
+    block: String,
+    level: u64,
+    sender: String,
+    outputs: Vec<(String, u32)>,
+    account_nonce: u32,
+    memo: String,
+    // preimage revealed by an HTLC claim, hex
+    preimage: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ReceiptResponse {
+    hash: String,
+    block: String,
+    applied: bool,
+    failure: Option<String>,
+    account_nonce: u32,
+    balance_before: u32,
+    balance_after: u32,
+}
+
+impl ReceiptResponse {
+    fn new(block: &H256, receipt: &Receipt) -> Self {
+        ReceiptResponse {
+            hash: receipt.tx_hash.to_string(),
+            block: block.to_string(),
+            applied: receipt.is_applied(),
+            failure: receipt.failure.map(|f| f.to_string()),
+            account_nonce: receipt.account_nonce,
+            balance_before: receipt.balance_before,
+            balance_after: receipt.balance_after,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct MinerStatusResponse {
+    state: String,
+    lambda: Option<u64>,
+    strategy: String,
+    withheld_blocks: usize,
+    hashrate: f64,
+    blocks_mined: u64,
+    stale_blocks: u64,
+    template_parent: Option<String>,
+    template_transactions: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct HtlcLockResponse {
+    id: String,
+    sender: String,
+    receiver: String,
+    amount: u32,
+    hashlock: String,
+    timeout: u64,
+}
+
+#[derive(Serialize)]
+struct TransactionProof {
+    hash: String,
+    block: String,
+    merkle_root: String,
+    index: usize,
+    leaf_size: usize,
+    siblings: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct StateProof {
+    block: String,
+    state_root: String,
+    address: String,
+    account_nonce: Option<u32>,
+    balance: Option<u32>,
+    bitmap: String,
+    siblings: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct MempoolStatsResponse {
+    count: usize,
+    bytes: u64,
+    oldest_hash: Option<String>,
+    oldest_age_ms: Option<u128>,
+    sender_depth: HashMap<String, usize>,
+}
+
+/// Parse a 32-byte hash written as 64 hex characters
+fn parse_hash(s: &str) -> Result<H256, String> {
+    let bytes = hex::decode(s).map_err(|e| e.to_string())?;
+    if bytes.len() != 32 {
+        return Err(format!("expected 32 bytes, got {}", bytes.len()));
+    }
+    let mut buffer: [u8; 32] = [0; 32];
+    buffer.copy_from_slice(&bytes);
+    Ok(H256::from(buffer))
+}
+
 macro_rules! respond_result {
     ( $req:expr, $success:expr, $message:expr ) => {{
         let content_type = "Content-Type: application/json".parse::<Header>().unwrap();
@@ -56,6 +179,7 @@ impl Server {
         generator: &GeneratorHandle,
         network: &NetworkServerHandle,
         blockchain: &Arc<Mutex<Blockchain>>,
+        mempool: &Arc<Mutex<Mempool>>,
     ) {
         let handle = HTTPServer::http(&addr).unwrap();
         let server = Self {
@@ -64,6 +188,7 @@ impl Server {
             generator: generator.clone(),
             network: network.clone(),
             blockchain: Arc::clone(blockchain),
+            mempool: Arc::clone(mempool),
         };
         thread::spawn(move || {
             for req in server.handle.incoming_requests() {
@@ -71,6 +196,7 @@ impl Server {
                 let generator = server.generator.clone();
                 let network = server.network.clone();
                 let blockchain = Arc::clone(&server.blockchain);
+                let mempool = Arc::clone(&server.mempool);
                 thread::spawn(move || {
                     // a valid url requires a base
                     let base_url = Url::parse(&format!("http://{}/", &addr)).unwrap();
@@ -103,9 +229,103 @@ impl Server {
                                     return;
                                 }
                             };
-                            miner.start(lambda);
+                            let strategy = match params.get("strategy").map(|v| v.as_str()) {
+                                None | Some("honest") => Strategy::Honest,
+                                Some("selfish") => Strategy::Selfish,
+                                Some("fork") => {
+                                    let height = match params.get("height").map(|v| v.parse::<u64>()) {
+                                        Some(Ok(v)) => v,
+                                        Some(Err(e)) => {
+                                            respond_result!(req, false, format!("error parsing height: {}", e));
+                                            return;
+                                        }
+                                        None => {
+                                            respond_result!(req, false, "missing height");
+                                            return;
+                                        }
+                                    };
+                                    if height > blockchain.lock().unwrap().tip_level {
+                                        respond_result!(req, false, "height is beyond the longest chain");
+                                        return;
+                                    }
+                                    Strategy::ForkFromHeight(height)
+                                }
+                                Some(v) => {
+                                    respond_result!(req, false, format!("unknown strategy {}", v));
+                                    return;
+                                }
+                            };
+                            if !miner.start_with_strategy(lambda, strategy) {
+                                respond_result!(req, false, "miner has been stopped");
+                                return;
+                            }
+                            respond_result!(req, true, "ok");
+                        }
+                        "/miner/pause" => {
+                            if !miner.pause() {
+                                respond_result!(req, false, "miner has been stopped");
+                                return;
+                            }
                             respond_result!(req, true, "ok");
                         }
+                        "/miner/stop" => {
+                            miner.exit();
+                            respond_result!(req, true, "ok");
+                        }
+                        "/miner/generate" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let count = match params.get("count") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing count");
+                                    return;
+                                }
+                            };
+                            let count = match count.parse::<u64>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing count: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            if !blockchain.lock().unwrap().regtest {
+                                respond_result!(req, false, "blocks can only be generated in regtest mode");
+                                return;
+                            }
+                            let blocks = match generate_blocks(&blockchain, &mempool, count) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(req, false, e);
+                                    return;
+                                }
+                            };
+                            let hashes: Vec<H256> = blocks.iter().map(|block| block.hash()).collect();
+                            if !hashes.is_empty() {
+                                network.broadcast(Message::NewBlockHashes(hashes.clone()));
+                            }
+                            let hashes: Vec<String> = hashes.iter().map(|hash| hash.to_string()).collect();
+                            respond_json!(req, hashes);
+                        }
+                        "/miner/status" => {
+                            let status = miner.status();
+                            let response = MinerStatusResponse {
+                                state: status.state.to_string(),
+                                lambda: status.lambda,
+                                strategy: status.strategy.to_string(),
+                                withheld_blocks: status.withheld_blocks,
+                                hashrate: status.hashrate,
+                                blocks_mined: status.blocks_mined,
+                                stale_blocks: status.stale_blocks,
+                                template_parent: status.template.map(|(parent, _)| parent.to_string()),
+                                template_transactions: status.template.map(|(_, count)| count),
+                            };
+                            respond_json!(req, response);
+                        }
                         "/tx-generator/start" => {
                             let params = url.query_pairs();
                             let params: HashMap<_, _> = params.into_owned().collect();
@@ -203,6 +423,323 @@ impl Server {
                             
                         }
                         
+                        "/mempool/transactions" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let sender = match params.get("sender") {
+                                Some(v) => match v.parse::<Address>() {
+                                    Ok(addr) => Some(addr),
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing sender: {}", e)
+                                        );
+                                        return;
+                                    }
+                                },
+                                None => None,
+                            };
+                            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+                            let entries = mempool.lock().unwrap().entries(sender);
+                            let v: Vec<MempoolTransaction> = entries
+                                .into_iter()
+                                .map(|(hash, st, inserted)| {
+                                    let transaction = st.get_transaction();
+                                    MempoolTransaction {
+                                        hash: hash.to_string(),
+                                        sender: transaction.get_sender().to_string(),
+                                        outputs: transaction
+                                            .get_outputs()
+                                            .into_iter()
+                                            .map(|(addr, value)| (addr.to_string(), value))
+                                            .collect(),
+                                        value: transaction.get_total_value().unwrap_or(0),
+                                        account_nonce: transaction.get_account_nonce(),
+                                        size: bincode::serialized_size(&st).unwrap(),
+                                        age_ms: now.saturating_sub(inserted),
+                                        memo: hex::encode(transaction.get_memo()),
+                                        lock_height: transaction.get_lock_height(),
+                                        lock_time: transaction.get_lock_time(),
+                                    }
+                                })
+                                .collect();
+                            respond_json!(req, v);
+                        }
+                        "/blockchain/transaction" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing hash");
+                                    return;
+                                }
+                            };
+                            let hash = match parse_hash(hash) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let found = blockchain.lock().unwrap().find_transaction(&hash);
+                            match found {
+                                Some((block_hash, level, st)) => {
+                                    let transaction = st.get_transaction();
+                                    let payload = ChainTransaction {
+                                        hash: hash.to_string(),
+                                        block: block_hash.to_string(),
+                                        level,
+                                        sender: transaction.get_sender().to_string(),
+                                        outputs: transaction
+                                            .get_outputs()
+                                            .into_iter()
+                                            .map(|(addr, value)| (addr.to_string(), value))
+                                            .collect(),
+                                        account_nonce: transaction.get_account_nonce(),
+                                        memo: hex::encode(transaction.get_memo()),
+                                        preimage: match transaction.get_htlc() {
+                                            Some(HtlcAction::Claim { preimage, .. }) => Some(hex::encode(preimage)),
+                                            _ => None,
+                                        },
+                                    };
+                                    respond_json!(req, payload);
+                                }
+                                None => {
+                                    respond_result!(req, false, "transaction not found in longest chain");
+                                }
+                            }
+                        }
+                        "/blockchain/receipt" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing hash");
+                                    return;
+                                }
+                            };
+                            let hash = match parse_hash(hash) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let found = blockchain.lock().unwrap().find_receipt(&hash);
+                            match found {
+                                Some((block_hash, receipt)) => {
+                                    respond_json!(req, ReceiptResponse::new(&block_hash, &receipt));
+                                }
+                                None => {
+                                    respond_result!(req, false, "transaction not found in longest chain");
+                                }
+                            }
+                        }
+                        "/blockchain/receipts" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let block = match params.get("block") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing block");
+                                    return;
+                                }
+                            };
+                            let block = match block.parse::<usize>() {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing block: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let blockchain = blockchain.lock().unwrap();
+                            let longest_chain_vector = blockchain.all_blocks_in_longest_chain();
+                            let block_hash = match longest_chain_vector.get(block) {
+                                Some(h) => *h,
+                                None => {
+                                    respond_result!(req, false, "block is beyond the longest chain");
+                                    return;
+                                }
+                            };
+                            let v: Vec<ReceiptResponse> = blockchain.receipt_map[&block_hash]
+                                .iter()
+                                .map(|receipt| ReceiptResponse::new(&block_hash, receipt))
+                                .collect();
+                            drop(blockchain);
+                            respond_json!(req, v);
+                        }
+                        "/blockchain/htlc" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let id = match params.get("id") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing id");
+                                    return;
+                                }
+                            };
+                            let id = match parse_hash(id) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing id: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let lock = {
+                                let blockchain = blockchain.lock().unwrap();
+                                blockchain.htlc_map[&blockchain.tip()].get(&id).cloned()
+                            };
+                            match lock {
+                                Some(lock) => {
+                                    let payload = HtlcLockResponse {
+                                        id: id.to_string(),
+                                        sender: lock.sender.to_string(),
+                                        receiver: lock.receiver.to_string(),
+                                        amount: lock.amount,
+                                        hashlock: lock.hashlock.to_string(),
+                                        timeout: lock.timeout,
+                                    };
+                                    respond_json!(req, payload);
+                                }
+                                None => {
+                                    respond_result!(req, false, "no open lock with this id at the tip");
+                                }
+                            }
+                        }
+                        "/blockchain/tx-proof" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let hash = match params.get("hash") {
+                                Some(v) => v,
+                                None => {
+                                    respond_result!(req, false, "missing hash");
+                                    return;
+                                }
+                            };
+                            let hash = match parse_hash(hash) {
+                                Ok(v) => v,
+                                Err(e) => {
+                                    respond_result!(
+                                        req,
+                                        false,
+                                        format!("error parsing hash: {}", e)
+                                    );
+                                    return;
+                                }
+                            };
+                            let found = blockchain.lock().unwrap().transaction_proof(&hash);
+                            match found {
+                                Some((block_hash, merkle_root, proof)) => {
+                                    let payload = TransactionProof {
+                                        hash: hash.to_string(),
+                                        block: block_hash.to_string(),
+                                        merkle_root: merkle_root.to_string(),
+                                        index: proof.index,
+                                        leaf_size: proof.leaf_size,
+                                        siblings: proof.siblings.iter().map(|h| h.to_string()).collect(),
+                                    };
+                                    respond_json!(req, payload);
+                                }
+                                None => {
+                                    respond_result!(req, false, "transaction not found in longest chain");
+                                }
+                            }
+                        }
+                        "/blockchain/state-proof" => {
+                            let params = url.query_pairs();
+                            let params: HashMap<_, _> = params.into_owned().collect();
+                            let address = match params.get("address") {
+                                Some(v) => match v.parse::<Address>() {
+                                    Ok(addr) => addr,
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing address: {}", e)
+                                        );
+                                        return;
+                                    }
+                                },
+                                None => {
+                                    respond_result!(req, false, "missing address");
+                                    return;
+                                }
+                            };
+                            let blockchain = blockchain.lock().unwrap();
+                            let longest_chain_vector = blockchain.all_blocks_in_longest_chain();
+                            // same as /blockchain/state: `block` is a level in the longest chain, the tip by default
+                            let block = match params.get("block") {
+                                Some(v) => match v.parse::<usize>() {
+                                    Ok(v) => v,
+                                    Err(e) => {
+                                        respond_result!(
+                                            req,
+                                            false,
+                                            format!("error parsing block: {}", e)
+                                        );
+                                        return;
+                                    }
+                                },
+                                None => longest_chain_vector.len() - 1,
+                            };
+                            let block_hash = match longest_chain_vector.get(block) {
+                                Some(h) => *h,
+                                None => {
+                                    respond_result!(req, false, "block is beyond the longest chain");
+                                    return;
+                                }
+                            };
+                            let tree = blockchain.state_tree(&block_hash).unwrap();
+                            let account = blockchain.state_map[&block_hash].get(&address).copied();
+                            drop(blockchain);
+                            let proof = tree.proof(&address);
+                            let payload = StateProof {
+                                block: block_hash.to_string(),
+                                state_root: tree.root().to_string(),
+                                address: address.to_string(),
+                                account_nonce: account.map(|(account_nonce, _)| account_nonce),
+                                balance: account.map(|(_, balance)| balance),
+                                bitmap: hex::encode(proof.bitmap.0),
+                                siblings: proof.siblings.iter().map(|h| h.to_string()).collect(),
+                            };
+                            respond_json!(req, payload);
+                        }
+                        "/mempool/stats" => {
+                            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+                            let stats = mempool.lock().unwrap().stats();
+                            let payload = MempoolStatsResponse {
+                                count: stats.count,
+                                bytes: stats.bytes,
+                                oldest_hash: stats.oldest.map(|(hash, _)| hash.to_string()),
+                                oldest_age_ms: stats.oldest.map(|(_, inserted)| now.saturating_sub(inserted)),
+                                sender_depth: stats
+                                    .sender_depth
+                                    .into_iter()
+                                    .map(|(addr, depth)| (addr.to_string(), depth))
+                                    .collect(),
+                            };
+                            respond_json!(req, payload);
+                        }
+
                         _ => {
                             let content_type =
                                 "Content-Type: application/json".parse::<Header>().unwrap();
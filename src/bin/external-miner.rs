@@ -0,0 +1,132 @@
+//! Reference external miner for the getwork interface of a node started with `--getwork`.
+//!
+//! It only depends on the protocol: fetch a template, try nonces at `nonce_offset` until the
+//! SHA256 of the header is at most the target, then submit the nonce.
+
+use clap::clap_app;
+use serde_json::{json, Value};
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::process;
+use std::thread;
+use std::time;
+
+struct Work {
+    id: u64,
+    header: Vec<u8>,
+    nonce_offset: usize,
+    target: Vec<u8>,
+}
+
+struct Connection {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Connection {
+    fn open(addr: &str) -> std::io::Result<Self> {
+        let writer = TcpStream::connect(addr)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Connection { reader, writer })
+    }
+
+    fn call(&mut self, request: Value) -> std::io::Result<Value> {
+        self.writer.write_all(format!("{}\n", request).as_bytes())?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "node closed the connection"));
+        }
+        serde_json::from_str(&line).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+
+    /// Ask for a template; `Ok(None)` when the node has nothing to mine
+    fn getwork(&mut self) -> std::io::Result<Option<Work>> {
+        let reply = self.call(json!({"method": "getwork"}))?;
+        if let Some(error) = reply.get("error") {
+            eprintln!("No work: {}", error);
+            return Ok(None);
+        }
+        let field = |name: &str| reply.get(name).cloned().unwrap_or(Value::Null);
+        let decode = |name: &str| hex::decode(field(name).as_str().unwrap_or_default()).unwrap_or_default();
+        let work = Work {
+            id: field("id").as_u64().unwrap_or_default(),
+            header: decode("header"),
+            nonce_offset: field("nonce_offset").as_u64().unwrap_or_default() as usize,
+            target: decode("target"),
+        };
+        if work.target.len() != 32 || work.header.len() < work.nonce_offset + 4 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed work"));
+        }
+        Ok(Some(work))
+    }
+}
+
+/// Try nonces from zero for at most `duration`, returning the first one that meets the target
+fn search(work: &mut Work, duration: time::Duration) -> Option<u32> {
+    let deadline = time::Instant::now() + duration;
+    let mut nonce: u32 = 0;
+    loop {
+        work.header[work.nonce_offset..work.nonce_offset + 4].copy_from_slice(&nonce.to_be_bytes());
+        let hash = ring::digest::digest(&ring::digest::SHA256, &work.header);
+        if hash.as_ref() <= work.target.as_slice() {
+            return Some(nonce);
+        }
+        nonce = nonce.checked_add(1)?;
+        if nonce.is_multiple_of(4096) && time::Instant::now() >= deadline {
+            return None;
+        }
+    }
+}
+
+fn main() {
+    let matches = clap_app!(ExternalMiner =>
+     (version: "0.1")
+     (about: "Mines blocks for a node through its getwork interface")
+     (@arg node: --node [ADDR] default_value("127.0.0.1:8000") "Sets the getwork address of the node")
+     (@arg refresh: --refresh [MS] default_value("2000") "Sets how long to mine one template before asking for a fresh one")
+    )
+    .get_matches();
+
+    let node = matches.value_of("node").unwrap();
+    let refresh = matches
+        .value_of("refresh")
+        .unwrap()
+        .parse::<u64>()
+        .unwrap_or_else(|e| {
+            eprintln!("Error parsing refresh interval: {}", e);
+            process::exit(1);
+        });
+    let refresh = time::Duration::from_millis(refresh);
+
+    loop {
+        let mut connection = match Connection::open(node) {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("Error connecting to {}, retrying in one second: {}", node, e);
+                thread::sleep(time::Duration::from_millis(1000));
+                continue;
+            }
+        };
+        if let Err(e) = mine(&mut connection, refresh) {
+            eprintln!("Lost connection to {}: {}", node, e);
+        }
+    }
+}
+
+fn mine(connection: &mut Connection, refresh: time::Duration) -> std::io::Result<()> {
+    loop {
+        let mut work = match connection.getwork()? {
+            Some(work) => work,
+            None => {
+                thread::sleep(time::Duration::from_millis(500));
+                continue;
+            }
+        };
+        // mine the template until it is solved, its nonces run out, or it is time for a fresh one
+        if let Some(nonce) = search(&mut work, refresh) {
+            let reply = connection.call(json!({"method": "submit", "id": work.id, "nonce": nonce}))?;
+            println!("Submitted nonce {} for work {}: {}", nonce, work.id, reply);
+        }
+    }
+}
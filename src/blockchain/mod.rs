@@ -1,17 +1,26 @@
 use crate::miner::new;
+use crate::consensus::{Consensus, Pow};
 use crate::types::address::Address;
 use crate::types::block::{Block, generate_random_block_1, self};
 use crate::types::hash::{H256, Hashable};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use hex_literal::hex;
 use ring::rand::generate;
 use ring::signature::{Ed25519KeyPair, KeyPair};
 
-use crate::types::{merkle::MerkleTree, transaction::SignedTransaction,block::{Header,Content}};
+use crate::types::{merkle::{MerkleTree, MerkleProof, SparseMerkleTree}, transaction::{HtlcAction, SignedTransaction, Transaction, DEFAULT_CHAIN_ID},block::{Header,Content}};
 
 
 
 
+/// Leaf committed to in the state tree for an account with `(account_nonce, balance)`
+pub fn state_leaf(account_nonce: u32, balance: u32) -> H256 {
+    let bytes = [account_nonce.to_be_bytes(), balance.to_be_bytes()].concat();
+    ring::digest::digest(&ring::digest::SHA256, &bytes).into()
+}
+
 pub struct Blockchain {
     pub map: HashMap<H256, Block>,
     // Additional hashmap to store the level number of each block with their hash
@@ -19,38 +28,202 @@ pub struct Blockchain {
     pub tip_hash: H256,
     pub tip_level: u64, //genesis, level 0
 
-    pub state_map: HashMap<H256, HashMap<Address, (u32, u32)>> // format: (account_nonce, balance)
+    pub state_map: HashMap<H256, HashMap<Address, (u32, u32)>>, // format: (account_nonce, balance)
+    // open HTLC locks after each block, by the hash of the locking transaction
+    pub htlc_map: HashMap<H256, HashMap<H256, HtlcLock>>,
+    // outcome of each transaction of a block, in block order
+    pub receipt_map: HashMap<H256, Vec<Receipt>>,
+    // only transactions signed for this chain id are valid
+    pub chain_id: u32,
+    // total weight of the chain ending at each block; the tip is the heaviest block
+    pub weight_map: HashMap<H256, H256>,
+    pub consensus: Arc<dyn Consensus>,
+    // local test network, where blocks are generated on demand
+    pub regtest: bool,
 }
 
 pub struct Mempool{
     pub map: HashMap<H256, SignedTransaction>,
+    // Additional hashmap to store when each transaction entered the mempool (ms since UNIX epoch)
+    pub time_map: HashMap<H256, u128>,
+}
+
+/// Hashes of signed transactions whose signatures already passed `verify_signed`, so that
+/// transactions checked on mempool entry are not verified again when they arrive in a block.
+pub struct SignatureCache {
+    verified: HashSet<H256>,
+    // insertion order, oldest first, for evicting once `capacity` is reached
+    order: VecDeque<H256>,
+    capacity: usize,
+}
+
+impl SignatureCache {
+    pub fn new(capacity: usize) -> Self {
+        Self { verified: HashSet::new(), order: VecDeque::new(), capacity }
+    }
+    pub fn contains(&self, st_hash: &H256) -> bool {
+        self.verified.contains(st_hash)
+    }
+    pub fn insert(&mut self, st_hash: H256) {
+        if !self.verified.insert(st_hash) {
+            return;
+        }
+        self.order.push_back(st_hash);
+        while self.order.len() > self.capacity {
+            let oldest = self.order.pop_front().unwrap();
+            self.verified.remove(&oldest);
+        }
+    }
+}
+
+/// Funds locked by an `HtlcAction::Lock` transaction, held outside both accounts until claimed or refunded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HtlcLock {
+    pub sender: Address,
+    pub receiver: Address,
+    pub amount: u32,
+    pub hashlock: H256,
+    /// first block level at which the lock can be refunded, and no longer claimed
+    pub timeout: u64,
+}
+
+/// Why a transaction included in a block did not move any funds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionFailure {
+    /// the outputs add up to more than a u32
+    ValueOverflow,
+    /// the sender has no account in the parent state
+    UnknownSender,
+    InsufficientBalance,
+    /// the HTLC step does not fit the open locks (unknown lock, wrong preimage or party, timeout)
+    HtlcRejected,
+}
+
+impl std::fmt::Display for TransactionFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TransactionFailure::ValueOverflow => write!(f, "total output value overflows"),
+            TransactionFailure::UnknownSender => write!(f, "sender has no account"),
+            TransactionFailure::InsufficientBalance => write!(f, "insufficient balance"),
+            TransactionFailure::HtlcRejected => write!(f, "HTLC step not allowed"),
+        }
+    }
+}
+
+/// Outcome of a transaction included in a block. A failed transaction leaves the state, including
+/// the sender's nonce, unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Receipt {
+    pub tx_hash: H256,
+    pub failure: Option<TransactionFailure>,
+    /// account nonce carried by the transaction
+    pub account_nonce: u32,
+    /// sender balance before and after the transaction
+    pub balance_before: u32,
+    pub balance_after: u32,
+}
+
+impl Receipt {
+    pub fn is_applied(&self) -> bool {
+        self.failure.is_none()
+    }
+}
+
+/// Aggregate statistics over the pending transactions in the mempool.
+pub struct MempoolStats {
+    pub count: usize,
+    pub bytes: u64,
+    /// Hash and insertion time of the transaction that has been waiting the longest
+    pub oldest: Option<(H256, u128)>,
+    /// Number of pending transactions for each sender
+    pub sender_depth: HashMap<Address, usize>,
 }
 
 
 impl Mempool{
     pub fn new() -> Self{
         let mut new_map = HashMap::new();
-        Self {map: new_map}
+        Self {map: new_map, time_map: HashMap::new()}
     }
     pub fn insert(&mut self, st: &SignedTransaction) {
         let st_hash = st.clone().hash();
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+        // keep the original arrival time if the transaction is inserted again
+        self.time_map.entry(st_hash).or_insert(now);
         self.map.insert(st_hash,st.clone());
     }
+    pub fn remove(&mut self, st_hash: &H256) -> Option<SignedTransaction> {
+        self.time_map.remove(st_hash);
+        self.map.remove(st_hash)
+    }
+
+    /// Pending transactions (optionally only those from `sender`) with their insertion time, oldest first
+    pub fn entries(&self, sender: Option<Address>) -> Vec<(H256, SignedTransaction, u128)> {
+        let mut output: Vec<(H256, SignedTransaction, u128)> = Vec::new();
+        for (hash, st) in self.map.iter() {
+            if let Some(sender) = sender {
+                if st.get_transaction().get_sender() != sender {
+                    continue;
+                }
+            }
+            let inserted = self.time_map.get(hash).copied().unwrap_or(0);
+            output.push((*hash, st.clone(), inserted));
+        }
+        output.sort_by_key(|(_, _, inserted)| *inserted);
+        output
+    }
+
+    pub fn stats(&self) -> MempoolStats {
+        let mut bytes: u64 = 0;
+        let mut oldest: Option<(H256, u128)> = None;
+        let mut sender_depth: HashMap<Address, usize> = HashMap::new();
+        for (hash, st) in self.map.iter() {
+            bytes += bincode::serialized_size(st).unwrap();
+            *sender_depth.entry(st.get_transaction().get_sender()).or_insert(0) += 1;
+            let inserted = self.time_map.get(hash).copied().unwrap_or(0);
+            match oldest {
+                Some((_, oldest_time)) if oldest_time <= inserted => {}
+                _ => oldest = Some((*hash, inserted)),
+            }
+        }
+        MempoolStats { count: self.map.len(), bytes, oldest, sender_depth }
+    }
 }
 
 impl Blockchain {
     /// Create a new blockchain, only containing the genesis block
     pub fn new() -> Self {
+        Self::new_with_chain_id(DEFAULT_CHAIN_ID)
+    }
+
+    /// Create a new blockchain, only containing the genesis block, that accepts transactions signed for `chain_id`
+    pub fn new_with_chain_id(chain_id: u32) -> Self {
+        Self::new_with_consensus(chain_id, Arc::new(Pow))
+    }
+
+    /// Create a new blockchain, only containing the genesis block, whose blocks are sealed by `consensus`
+    pub fn new_with_consensus(chain_id: u32, consensus: Arc<dyn Consensus>) -> Self {
+        Self::with_genesis_difficulty(chain_id, consensus, hex!("000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff").into())
+    }
+
+    /// Create a regtest blockchain: its difficulty lets any hash through, and blocks can be generated on demand.
+    /// Its genesis block differs from the one of `new_with_consensus`, so the two never connect.
+    pub fn new_regtest(chain_id: u32, consensus: Arc<dyn Consensus>) -> Self {
+        let mut blockchain = Self::with_genesis_difficulty(chain_id, consensus, H256::max_value());
+        blockchain.regtest = true;
+        blockchain
+    }
+
+    fn with_genesis_difficulty(chain_id: u32, consensus: Arc<dyn Consensus>, dify: H256) -> Self {
         let zeros: [u8; 32] = [0;32];
         let parent: H256 = H256::from(zeros);
         
         let mut new_map = HashMap::new();
         let noncy: u32 = 1;
-        let dify: H256 = hex!("000fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff").into();
         let timy: u128 = 0;
         let empty: Vec<H256> = Vec::new();
         let merkly = MerkleTree::new(&empty).root();
-        let heady= Header{parent: parent, nonce: noncy, difficulty: dify, timestamp: timy, merkle_root: merkly};
+        let heady= Header{version: block::HEADER_VERSION, parent: parent, nonce: noncy, extra_nonce: 0, difficulty: dify, timestamp: timy, merkle_root: merkly, seal: Vec::new()};
         let vec:Vec<SignedTransaction> = Vec::new();
         let no_content = Content(vec);
         let genesis = Block{header: heady, content: no_content};
@@ -96,11 +269,38 @@ impl Blockchain {
         new_state_map.insert(genesis_hash_copy_2,genesis_state);
 
 
-        Self {map: new_map, level_map: new_level_map, tip_hash: genesis_hash, tip_level: level, state_map: new_state_map}
+        let mut new_htlc_map: HashMap<H256, HashMap<H256, HtlcLock>> = HashMap::new();
+        new_htlc_map.insert(genesis_hash_copy_2, HashMap::new());
+
+        let mut new_receipt_map: HashMap<H256, Vec<Receipt>> = HashMap::new();
+        new_receipt_map.insert(genesis_hash_copy_2, Vec::new());
+
+        let mut new_weight_map: HashMap<H256, H256> = HashMap::new();
+        new_weight_map.insert(genesis_hash, H256::default());
+
+        Self {map: new_map, level_map: new_level_map, tip_hash: genesis_hash, tip_level: level, state_map: new_state_map, htlc_map: new_htlc_map, receipt_map: new_receipt_map, chain_id, weight_map: new_weight_map, consensus, regtest: false}
     }
 
     /// Insert a block into blockchain
     pub fn insert(&mut self, block: &Block) {
+        self.insert_block(block, true);
+    }
+
+    /// Insert a block mined by a withholding strategy, keeping it out of the fork choice until `reveal`
+    /// is called, so the tip stays on published blocks
+    pub fn insert_withheld(&mut self, block: &Block) {
+        self.insert_block(block, false);
+    }
+
+    /// Let a block inserted by `insert_withheld` become the tip if it is the heaviest
+    pub fn reveal(&mut self, block_hash: &H256) {
+        if self.weight_map[block_hash] > self.weight_map[&self.tip_hash] {
+            self.tip_hash = *block_hash;
+            self.tip_level = self.level_map[block_hash];
+        }
+    }
+
+    fn insert_block(&mut self, block: &Block, fork_choice: bool) {
         let block_hash = block.hash();
         let block_hash_copy = block_hash.clone();
         let block_hash_copy2 = block_hash.clone();
@@ -118,8 +318,12 @@ impl Blockchain {
         if block_level > self.tip_level +1{
             println!("Block Level is too big! (>1 + tip_level)")
         }
-        if block_level > self.tip_level{
-            self.tip_level = self.tip_level+1;
+        // fork choice: follow the heaviest chain, the first block seen winning ties
+        let parent_weight = self.weight_map.get(&parent_hash).copied().unwrap_or_default();
+        let block_weight = parent_weight.checked_add(&self.consensus.weight(&block.header)).unwrap_or_else(H256::max_value);
+        self.weight_map.insert(block_hash, block_weight);
+        if fork_choice && block_weight > self.weight_map[&self.tip_hash] {
+            self.tip_level = block_level;
             self.tip_hash = block_hash_copy2;
         }
         
@@ -128,44 +332,18 @@ impl Blockchain {
 
 
         let mut block_state: HashMap<Address, (u32, u32)> = self.state_map.get(&parent_hash2).unwrap().clone();
+        let mut block_locks: HashMap<H256, HtlcLock> = self.htlc_map.get(&parent_hash2).unwrap().clone();
         // go through previous state and change the values in any account in which there was a transaction:
         let block_details = block.get_transaction_details();
 
-        for i in block_details.into_iter(){
-            let transaction = i.get_transaction();
-            let sendery = transaction.get_sender();
-            let recievery = transaction.get_reciever();
-            let valuey = transaction.get_value();
-
-            if block_state.contains_key(&recievery){
-                let (send_an, send_bal) = *block_state.get(&sendery).unwrap();
-                let (recieve_an, recieve_bal) = *block_state.get(&recievery).unwrap();
-                let new_san = send_an +1;
-                if send_bal >= valuey{
-                    let new_send_bal = send_bal - valuey.clone();
-                    let new_recieve_bal = recieve_bal+valuey.clone();
-
-                    *block_state.get_mut(&sendery).unwrap() = (new_san,new_send_bal); 
-                    *block_state.get_mut(&recievery).unwrap() = (recieve_an,new_recieve_bal);
-                }
-                
-
-            }
-            else{
-                let (send_an, send_bal) = *block_state.get(&sendery).unwrap();
-                let new_san = send_an +1;
-
-                if send_bal >= valuey {
-                    let new_send_bal = send_bal - valuey.clone();
-
-                    *block_state.get_mut(&sendery).unwrap() = (new_san,new_send_bal); 
-                    block_state.insert(recievery.clone(), (0,valuey.clone()));
-                }
-                
-            }
+        let mut receipts: Vec<Receipt> = Vec::new();
+        for i in block_details.iter(){
+            receipts.push(self.apply_transaction(i, &parent_hash2, &mut block_state, &mut block_locks));
         }
         
         self.state_map.insert(block_hash_copy3,block_state);
+        self.htlc_map.insert(block_hash, block_locks);
+        self.receipt_map.insert(block_hash, receipts);
         
         let block_copy = block.clone();
 
@@ -175,6 +353,75 @@ impl Blockchain {
 
     }
 
+    /// Apply one transaction of a block whose parent is `parent` to the state being built for that block,
+    /// returning its receipt. Transactions that fail change nothing.
+    fn apply_transaction(&self, st: &SignedTransaction, parent: &H256, block_state: &mut HashMap<Address, (u32, u32)>, block_locks: &mut HashMap<H256, HtlcLock>) -> Receipt {
+        let transaction = st.get_transaction();
+        let sendery = transaction.get_sender();
+        let (send_an, send_bal) = block_state.get(&sendery).copied().unwrap_or((0, 0));
+        let mut receipt = Receipt {
+            tx_hash: st.hash(),
+            failure: None,
+            account_nonce: transaction.get_account_nonce(),
+            balance_before: send_bal,
+            balance_after: send_bal,
+        };
+        // batch payments are applied atomically: either every output is paid or none is
+        let valuey = match transaction.get_total_value() {
+            Some(v) => v,
+            None => {
+                receipt.failure = Some(TransactionFailure::ValueOverflow);
+                return receipt;
+            }
+        };
+        if !block_state.contains_key(&sendery) {
+            receipt.failure = Some(TransactionFailure::UnknownSender);
+            return receipt;
+        }
+        if !self.htlc_allowed(&transaction, parent) {
+            receipt.failure = Some(TransactionFailure::HtlcRejected);
+            return receipt;
+        }
+        if send_bal < valuey {
+            receipt.failure = Some(TransactionFailure::InsufficientBalance);
+            return receipt;
+        }
+
+        *block_state.get_mut(&sendery).unwrap() = (send_an + 1, send_bal - valuey);
+        match transaction.get_htlc() {
+            None => {
+                for (recievery, output_value) in transaction.get_outputs().into_iter(){
+                    let (_, recieve_bal) = block_state.entry(recievery).or_insert((0,0));
+                    *recieve_bal += output_value;
+                }
+            }
+            Some(HtlcAction::Lock { hashlock, timeout }) => {
+                let receiver = transaction.get_reciever();
+                block_locks.insert(receipt.tx_hash, HtlcLock { sender: sendery, receiver, amount: valuey, hashlock, timeout });
+                // the receiver needs an account to send the claim from
+                block_state.entry(receiver).or_insert((0,0));
+            }
+            Some(HtlcAction::Claim { lock_id, .. }) | Some(HtlcAction::Refund { lock_id }) => {
+                if let Some(lock) = block_locks.remove(&lock_id) {
+                    let (_, recieve_bal) = block_state.get_mut(&sendery).unwrap();
+                    *recieve_bal += lock.amount;
+                }
+            }
+        }
+        receipt.balance_after = block_state[&sendery].1;
+        receipt
+    }
+
+    /// Receipt of a transaction in the longest chain, with the hash of its block
+    pub fn find_receipt(&self, tx_hash: &H256) -> Option<(H256, Receipt)> {
+        for block_hash in self.all_blocks_in_longest_chain().into_iter(){
+            if let Some(receipt) = self.receipt_map[&block_hash].iter().find(|r| r.tx_hash == *tx_hash) {
+                return Some((block_hash, receipt.clone()));
+            }
+        }
+        None
+    }
+
     /// Get the last block's hash of the longest chain
     pub fn tip(&self) -> H256 {
         self.tip_hash.clone()
@@ -195,6 +442,94 @@ impl Blockchain {
         new_vec
     }
 
+    /// Median timestamp of the block `hash` and up to 10 of its ancestors
+    pub fn median_time_past(&self, hash: &H256) -> u128 {
+        let mut timestamps: Vec<u128> = Vec::new();
+        let mut temp_hash = *hash;
+        while timestamps.len() < 11 {
+            match self.map.get(&temp_hash) {
+                Some(block) => {
+                    timestamps.push(block.header.timestamp);
+                    temp_hash = block.get_parent();
+                }
+                None => break,
+            }
+        }
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
+    }
+
+    /// Whether the time locks of a transaction allow it in a block whose parent is `parent`:
+    /// the parent must be at least at its lock height and its median time past its lock time.
+    pub fn is_unlocked(&self, transaction: &Transaction, parent: &H256) -> bool {
+        let parent_level = match self.level_map.get(parent) {
+            Some(level) => *level,
+            None => return false,
+        };
+        parent_level >= transaction.get_lock_height() && self.median_time_past(parent) >= transaction.get_lock_time()
+    }
+
+    /// Whether the HTLC step of a transaction, if any, is allowed in a block whose parent is `parent`,
+    /// judged against the locks open after the parent.
+    pub fn htlc_allowed(&self, transaction: &Transaction, parent: &H256) -> bool {
+        let height = match self.level_map.get(parent) {
+            Some(level) => *level + 1,
+            None => return false,
+        };
+        let sender = transaction.get_sender();
+        match transaction.get_htlc() {
+            None => true,
+            Some(HtlcAction::Lock { timeout, .. }) => !transaction.is_batch() && timeout > height,
+            Some(HtlcAction::Claim { lock_id, preimage }) => match self.htlc_map[parent].get(&lock_id) {
+                Some(lock) => {
+                    let hash: H256 = ring::digest::digest(&ring::digest::SHA256, &preimage).into();
+                    transaction.get_total_value() == Some(0) && lock.receiver == sender && hash == lock.hashlock && height < lock.timeout
+                }
+                None => false,
+            },
+            Some(HtlcAction::Refund { lock_id }) => match self.htlc_map[parent].get(&lock_id) {
+                Some(lock) => transaction.get_total_value() == Some(0) && lock.sender == sender && height >= lock.timeout,
+                None => false,
+            },
+        }
+    }
+
+    /// Find a transaction in the longest chain, returning the hash and level of its block
+    pub fn find_transaction(&self, tx_hash: &H256) -> Option<(H256, u64, SignedTransaction)> {
+        for block_hash in self.all_blocks_in_longest_chain().into_iter(){
+            let block = self.map.get(&block_hash).unwrap();
+            for st in block.get_transaction_details().into_iter(){
+                if st.hash() == *tx_hash {
+                    return Some((block_hash, self.level_map[&block_hash], st));
+                }
+            }
+        }
+        None
+    }
+
+    /// Merkle proof that a transaction is included in a block of the longest chain, with that block's hash and merkle root
+    pub fn transaction_proof(&self, tx_hash: &H256) -> Option<(H256, H256, MerkleProof)> {
+        for block_hash in self.all_blocks_in_longest_chain().into_iter(){
+            let block = self.map.get(&block_hash).unwrap();
+            let tx_hashes = block.get_transactions();
+            if let Some(index) = tx_hashes.iter().position(|h| h == tx_hash) {
+                let proof = MerkleTree::new(&block.get_transaction_details()).merkle_proof(index);
+                return Some((block_hash, block.header.merkle_root, proof));
+            }
+        }
+        None
+    }
+
+    /// Sparse Merkle tree of the account state after `block_hash`, with `state_leaf` values as leaves
+    pub fn state_tree(&self, block_hash: &H256) -> Option<SparseMerkleTree> {
+        let state = self.state_map.get(block_hash)?;
+        let mut tree = SparseMerkleTree::new();
+        for (address, (account_nonce, balance)) in state.iter() {
+            tree.insert(*address, state_leaf(*account_nonce, *balance));
+        }
+        Some(tree)
+    }
+
     // Get alll tx hashes in longest chain
     pub fn all_tx_in_longest_chain(&self) -> Vec<Vec<H256>> {
         let block_hashes = self.all_blocks_in_longest_chain();
@@ -208,14 +543,39 @@ impl Blockchain {
 
 }
 
-/* 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::block::generate_random_block;
+    use crate::types::block::{generate_block, generate_random_block};
     use crate::types::hash::Hashable;
+    use crate::types::key_pair;
+    use crate::types::transaction::{sign, sig_to_vec};
+
+    /// Key of the account funded in the genesis state
+    fn genesis_key() -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[0; 32]).unwrap()
+    }
+
+    fn address_of(key: &Ed25519KeyPair) -> Address {
+        Address::from_public_key_bytes(key.public_key().as_ref())
+    }
+
+    fn signed(transaction: Transaction, key: &Ed25519KeyPair) -> SignedTransaction {
+        SignedTransaction::new(transaction.clone(), sig_to_vec(sign(&transaction, key)), key.public_key().as_ref().to_vec())
+    }
+
+    /// Insert a block with `transactions` on `parent`, returning its hash
+    fn mine(blockchain: &mut Blockchain, parent: &H256, transactions: Vec<SignedTransaction>) -> H256 {
+        let block = generate_block(parent, &blockchain.map[parent].get_difficulty(), &transactions);
+        blockchain.insert(&block);
+        block.hash()
+    }
+
+    fn balance(blockchain: &Blockchain, block: &H256, address: &Address) -> u32 {
+        blockchain.state_map[block].get(address).map(|(_, balance)| *balance).unwrap_or(0)
+    }
 
     #[test]
     fn insert_one() {
@@ -226,7 +586,197 @@ mod tests {
         assert_eq!(blockchain.tip(), block.hash());
 
     }
+
+    #[test]
+    fn htlc_claim_and_refund() {
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.tip();
+        let (sender_key, receiver_key) = (genesis_key(), key_pair::random());
+        let (sender, receiver) = (address_of(&sender_key), address_of(&receiver_key));
+        let funds = balance(&blockchain, &genesis, &sender);
+        let preimage = b"secret".to_vec();
+        let hashlock: H256 = ring::digest::digest(&ring::digest::SHA256, &preimage).into();
+
+        // the lock takes the amount from the sender without paying the receiver yet
+        let lock = signed(Transaction::new(sender, receiver, 100, 1, DEFAULT_CHAIN_ID).with_htlc(HtlcAction::Lock { hashlock, timeout: 4 }), &sender_key);
+        let lock_id = lock.hash();
+        let locked = mine(&mut blockchain, &genesis, vec![lock]);
+        assert_eq!(balance(&blockchain, &locked, &sender), funds - 100);
+        assert_eq!(blockchain.state_map[&locked].get(&receiver), Some(&(0, 0)));
+        assert_eq!(blockchain.htlc_map[&locked][&lock_id].amount, 100);
+
+        let claim = |preimage: &[u8], from: Address, nonce: u32| {
+            Transaction::new(from, from, 0, nonce, DEFAULT_CHAIN_ID).with_htlc(HtlcAction::Claim { lock_id, preimage: preimage.to_vec() })
+        };
+        let refund = |from: Address, nonce: u32| Transaction::new(from, from, 0, nonce, DEFAULT_CHAIN_ID).with_htlc(HtlcAction::Refund { lock_id });
+
+        // before the timeout only the receiver can claim, with the right preimage, and nobody can refund
+        assert!(blockchain.htlc_allowed(&claim(&preimage, receiver, 1), &locked));
+        assert!(!blockchain.htlc_allowed(&claim(b"guess", receiver, 1), &locked));
+        assert!(!blockchain.htlc_allowed(&claim(&preimage, sender, 2), &locked));
+        assert!(!blockchain.htlc_allowed(&refund(sender, 2), &locked));
+        let claimed = mine(&mut blockchain, &locked, vec![signed(claim(&preimage, receiver, 1), &receiver_key)]);
+        assert_eq!(balance(&blockchain, &claimed, &receiver), 100);
+        assert!(blockchain.htlc_map[&claimed].is_empty());
+
+        // a spent lock can not be claimed again
+        let again = signed(claim(&preimage, receiver, 2), &receiver_key);
+        assert!(!blockchain.htlc_allowed(&again.get_transaction(), &claimed));
+        let rejected = mine(&mut blockchain, &claimed, vec![again]);
+        assert_eq!(blockchain.receipt_map[&rejected][0].failure, Some(TransactionFailure::HtlcRejected));
+        assert_eq!(balance(&blockchain, &rejected, &receiver), 100);
+
+        // on a fork without the claim, the lock times out at level 4: the last block it can be claimed in is at level 3
+        let level_2 = mine(&mut blockchain, &locked, Vec::new());
+        assert!(blockchain.htlc_allowed(&claim(&preimage, receiver, 1), &level_2));
+        assert!(!blockchain.htlc_allowed(&refund(sender, 2), &level_2));
+        let level_3 = mine(&mut blockchain, &level_2, Vec::new());
+        assert!(!blockchain.htlc_allowed(&claim(&preimage, receiver, 1), &level_3));
+        assert!(!blockchain.htlc_allowed(&refund(receiver, 1), &level_3));
+        assert!(blockchain.htlc_allowed(&refund(sender, 2), &level_3));
+        let refunded = mine(&mut blockchain, &level_3, vec![signed(refund(sender, 2), &sender_key)]);
+        assert_eq!(balance(&blockchain, &refunded, &sender), funds);
+        assert_eq!(balance(&blockchain, &refunded, &receiver), 0);
+    }
+
+    #[test]
+    fn receipts() {
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.tip();
+        let (funded_key, poor_key, unknown_key) = (genesis_key(), key_pair::random(), key_pair::random());
+        let (funded, poor, unknown) = (address_of(&funded_key), address_of(&poor_key), address_of(&unknown_key));
+        let funds = balance(&blockchain, &genesis, &funded);
+        let paid = mine(&mut blockchain, &genesis, vec![signed(Transaction::new(funded, poor, 5, 1, DEFAULT_CHAIN_ID), &funded_key)]);
+
+        let applied = signed(Transaction::new(funded, poor, 10, 2, DEFAULT_CHAIN_ID), &funded_key);
+        let overdrawn = signed(Transaction::new(poor, funded, 16, 1, DEFAULT_CHAIN_ID), &poor_key);
+        let no_account = signed(Transaction::new(unknown, funded, 1, 1, DEFAULT_CHAIN_ID), &unknown_key);
+        let no_lock = Transaction::new(poor, poor, 0, 1, DEFAULT_CHAIN_ID).with_htlc(HtlcAction::Refund { lock_id: H256::default() });
+        let no_lock = signed(no_lock, &poor_key);
+        // transactions apply in block order: the poor account holds 15 coins when it tries to send 16
+        let block = mine(&mut blockchain, &paid, vec![applied.clone(), overdrawn.clone(), no_account.clone(), no_lock.clone()]);
+
+        let (block_hash, receipt) = blockchain.find_receipt(&applied.hash()).unwrap();
+        assert_eq!(block_hash, block);
+        assert!(receipt.is_applied());
+        assert_eq!((receipt.account_nonce, receipt.balance_before, receipt.balance_after), (2, funds - 5, funds - 15));
+        let (block_hash, receipt) = blockchain.find_receipt(&overdrawn.hash()).unwrap();
+        assert_eq!(block_hash, block);
+        assert_eq!(receipt.failure, Some(TransactionFailure::InsufficientBalance));
+        assert_eq!((receipt.balance_before, receipt.balance_after), (15, 15));
+        let (_, receipt) = blockchain.find_receipt(&no_account.hash()).unwrap();
+        assert_eq!(receipt.failure, Some(TransactionFailure::UnknownSender));
+        assert_eq!((receipt.balance_before, receipt.balance_after), (0, 0));
+        let (_, receipt) = blockchain.find_receipt(&no_lock.hash()).unwrap();
+        assert_eq!(receipt.failure, Some(TransactionFailure::HtlcRejected));
+        // failed transactions leave the sender's nonce unchanged
+        assert_eq!(blockchain.state_map[&block][&poor], (0, 15));
+        assert_eq!(blockchain.state_map[&block][&funded].0, 2);
+
+        // a transaction only in a side fork has no receipt in the longest chain
+        let side = signed(Transaction::new(funded, unknown, 1, 2, DEFAULT_CHAIN_ID), &funded_key);
+        mine(&mut blockchain, &paid, vec![side.clone()]);
+        assert_eq!(blockchain.tip(), block);
+        assert!(blockchain.find_receipt(&side.hash()).is_none());
+    }
+
+    #[test]
+    fn time_locks() {
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.tip();
+        // timestamps of the blocks at levels 1 to 12, not in order; the genesis block has timestamp 0
+        let mut chain = vec![genesis];
+        for timestamp in [100, 50, 300, 200, 400, 600, 500, 700, 900, 800, 1000, 1100] {
+            let mut block = generate_block(chain.last().unwrap(), &blockchain.map[&genesis].get_difficulty(), &Vec::new());
+            block.header.timestamp = timestamp;
+            blockchain.insert(&block);
+            chain.push(block.hash());
+        }
+        assert_eq!(blockchain.median_time_past(&genesis), 0);
+        assert_eq!(blockchain.median_time_past(&chain[2]), 50);
+        // the window is the block and its 10 ancestors: levels 1 to 11, then 2 to 12
+        assert_eq!(blockchain.median_time_past(&chain[11]), 500);
+        assert_eq!(blockchain.median_time_past(&chain[12]), 600);
+
+        let key = genesis_key();
+        let payment = || Transaction::new(address_of(&key), address_of(&key), 1, 1, DEFAULT_CHAIN_ID);
+        let tip = chain[12];
+        assert!(blockchain.is_unlocked(&payment().with_lock_height(11), &tip));
+        assert!(blockchain.is_unlocked(&payment().with_lock_height(12), &tip));
+        assert!(!blockchain.is_unlocked(&payment().with_lock_height(13), &tip));
+        assert!(blockchain.is_unlocked(&payment().with_lock_time(599), &tip));
+        assert!(blockchain.is_unlocked(&payment().with_lock_time(600), &tip));
+        assert!(!blockchain.is_unlocked(&payment().with_lock_time(601), &tip));
+
+        // the miner leaves locked transactions in the mempool
+        let mut mempool = Mempool::new();
+        let locked = signed(payment().with_lock_height(13), &key);
+        let unlocked = signed(payment().with_lock_height(12), &key);
+        mempool.insert(&locked);
+        assert!(crate::miner::select_transactions(&blockchain, &mempool, &tip).is_empty());
+        mempool.insert(&unlocked);
+        let selected = crate::miner::select_transactions(&blockchain, &mempool, &tip);
+        assert_eq!(selected.iter().map(|st| st.hash()).collect::<Vec<H256>>(), vec![unlocked.hash()]);
+    }
+
+    #[test]
+    fn heaviest_chain_wins() {
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.tip();
+        let first = mine(&mut blockchain, &genesis, Vec::new());
+        let longer = mine(&mut blockchain, &first, Vec::new());
+        // a sibling as heavy as the first block seen does not take over
+        let tied = mine(&mut blockchain, &genesis, Vec::new());
+        assert_eq!(blockchain.weight_map[&tied], blockchain.weight_map[&first]);
+        assert_eq!(blockchain.tip(), longer);
+
+        // one block with a target 16^4 times harder outweighs two at the genesis target
+        let hard: H256 = hex!("00000000ffffffffffffffffffffffffffffffffffffffffffffffffffffffff").into();
+        let heavy = generate_block(&genesis, &hard, &Vec::new());
+        blockchain.insert(&heavy);
+        assert_eq!(blockchain.tip(), heavy.hash());
+        assert_eq!(blockchain.tip_level, 1);
+        assert_eq!(blockchain.all_blocks_in_longest_chain(), vec![genesis, heavy.hash()]);
+    }
+
+    #[test]
+    fn mempool_entries_and_stats() {
+        let (first_key, second_key) = (key_pair::random(), key_pair::random());
+        let (first, second) = (address_of(&first_key), address_of(&second_key));
+        let from_first: Vec<SignedTransaction> = (1..=2).map(|nonce| signed(Transaction::new(first, second, 1, nonce, DEFAULT_CHAIN_ID), &first_key)).collect();
+        let from_second = signed(Transaction::new(second, first, 1, 1, DEFAULT_CHAIN_ID), &second_key);
+        let mut mempool = Mempool::new();
+        let before = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
+        for st in [&from_first[0], &from_second, &from_first[1]] {
+            mempool.insert(st);
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        let inserted = mempool.time_map[&from_first[0].hash()];
+        assert!(inserted >= before);
+        // inserting again keeps the arrival time
+        mempool.insert(&from_first[0]);
+        assert_eq!(mempool.time_map[&from_first[0].hash()], inserted);
+
+        let hashes = |entries: Vec<(H256, SignedTransaction, u128)>| entries.into_iter().map(|(hash, _, _)| hash).collect::<Vec<H256>>();
+        assert_eq!(hashes(mempool.entries(None)), vec![from_first[0].hash(), from_second.hash(), from_first[1].hash()]);
+        assert_eq!(hashes(mempool.entries(Some(first))), vec![from_first[0].hash(), from_first[1].hash()]);
+        assert_eq!(hashes(mempool.entries(Some(second))), vec![from_second.hash()]);
+        let stats = mempool.stats();
+        assert_eq!(stats.count, 3);
+        assert_eq!(stats.oldest, Some((from_first[0].hash(), inserted)));
+        assert_eq!((stats.sender_depth[&first], stats.sender_depth[&second]), (2, 1));
+
+        let size = bincode::serialized_size(&from_first[0]).unwrap();
+        assert!(mempool.remove(&from_first[0].hash()).is_some());
+        assert!(!mempool.time_map.contains_key(&from_first[0].hash()));
+        let after = mempool.stats();
+        assert_eq!(after.count, 2);
+        assert_eq!(after.bytes, stats.bytes - size);
+        assert_eq!(after.oldest, Some((from_second.hash(), mempool.time_map[&from_second.hash()])));
+        assert_eq!(after.sender_depth[&first], 1);
+        mempool.remove(&from_second.hash());
+        assert!(!mempool.stats().sender_depth.contains_key(&second));
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
-*/
\ No newline at end of file
@@ -0,0 +1,62 @@
+//! Consensus engines: how blocks are sealed, how seals are checked and which fork is followed.
+
+pub mod poa;
+pub mod pow;
+
+use crate::types::block::Header;
+use crate::types::hash::H256;
+
+pub use self::poa::Poa;
+pub use self::pow::Pow;
+
+/// What a node should do to seal a block it produces
+pub enum Seal {
+    /// the seal has been written into the header
+    Sealed,
+    /// the header needs a nonce search, as in proof of work
+    Search,
+    /// this node may not produce the block
+    NotOurTurn,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SealError {
+    /// the difficulty is not the one of the parent
+    DifficultyChanged,
+    /// the hash is above the difficulty
+    TargetMissed,
+    /// a proof of work header carries a seal
+    UnexpectedSeal,
+    /// the seal is not a signature of the signer whose turn it is
+    BadSignature,
+}
+
+impl std::fmt::Display for SealError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SealError::DifficultyChanged => write!(f, "difficulty differs from the parent's"),
+            SealError::TargetMissed => write!(f, "hash is above the difficulty"),
+            SealError::UnexpectedSeal => write!(f, "proof of work header has a seal"),
+            SealError::BadSignature => write!(f, "seal is not signed by the signer in turn"),
+        }
+    }
+}
+
+impl std::error::Error for SealError {}
+
+pub trait Consensus: Send + Sync {
+    /// Short name, as given on the command line
+    fn name(&self) -> &'static str;
+
+    /// Seal `header`, a new block at `height`, or say what is left to do
+    fn seal(&self, header: &mut Header, height: u64) -> Seal;
+
+    /// Check the seal of `header`, a block at `height` whose parent is `parent`
+    fn verify_seal(&self, header: &Header, parent: &Header, height: u64) -> Result<(), SealError>;
+
+    /// Cheap check of a block whose parent is unknown, before keeping it as an orphan
+    fn plausible_seal(&self, header: &Header) -> bool;
+
+    /// Weight a block adds to its chain; the heaviest chain is followed
+    fn weight(&self, header: &Header) -> H256;
+}
@@ -0,0 +1,105 @@
+use ring::signature::{self, Ed25519KeyPair, KeyPair};
+
+use super::{Consensus, Seal, SealError};
+use crate::types::block::Header;
+use crate::types::hash::H256;
+
+/// Proof of authority: a fixed list of signers takes turns, the block at height `h` being sealed by
+/// signer `h % n` with an Ed25519 signature of the bare hash of its header. A block whose signer is
+/// offline is never produced, so every signer has to be running.
+pub struct Poa {
+    // Ed25519 public keys, in turn order
+    signers: Vec<Vec<u8>>,
+    // key of this node, if it is one of the signers
+    key: Option<Ed25519KeyPair>,
+}
+
+impl Poa {
+    /// Panics if `signers` is empty
+    pub fn new(signers: Vec<Vec<u8>>, key: Option<Ed25519KeyPair>) -> Self {
+        assert!(!signers.is_empty(), "proof of authority needs at least one signer");
+        Poa { signers, key }
+    }
+
+    /// Public key of the signer whose turn it is at `height`
+    pub fn signer_at(&self, height: u64) -> &[u8] {
+        &self.signers[(height % self.signers.len() as u64) as usize]
+    }
+}
+
+fn signed_by(header: &Header, public_key: &[u8]) -> bool {
+    signature::UnparsedPublicKey::new(&signature::ED25519, public_key)
+        .verify(header.bare_hash().as_ref(), &header.seal)
+        .is_ok()
+}
+
+impl Consensus for Poa {
+    fn name(&self) -> &'static str {
+        "poa"
+    }
+
+    fn seal(&self, header: &mut Header, height: u64) -> Seal {
+        match &self.key {
+            Some(key) if key.public_key().as_ref() == self.signer_at(height) => {
+                header.seal = key.sign(header.bare_hash().as_ref()).as_ref().to_vec();
+                Seal::Sealed
+            }
+            _ => Seal::NotOurTurn,
+        }
+    }
+
+    fn verify_seal(&self, header: &Header, parent: &Header, height: u64) -> Result<(), SealError> {
+        if header.difficulty != parent.difficulty {
+            return Err(SealError::DifficultyChanged);
+        }
+        if !signed_by(header, self.signer_at(height)) {
+            return Err(SealError::BadSignature);
+        }
+        Ok(())
+    }
+
+    fn plausible_seal(&self, header: &Header) -> bool {
+        self.signers.iter().any(|signer| signed_by(header, signer))
+    }
+
+    /// Every block counts the same, so the fork choice follows the longest chain
+    fn weight(&self, _header: &Header) -> H256 {
+        H256::from(1u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::block::generate_random_block;
+    use crate::types::hash::Hashable;
+
+    fn key(seed: u8) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap()
+    }
+
+    #[test]
+    fn signers_take_turns() {
+        let signers: Vec<Vec<u8>> = (1..=3).map(|seed| key(seed).public_key().as_ref().to_vec()).collect();
+        let second = Poa::new(signers.clone(), Some(key(2)));
+        let observer = Poa::new(signers, None);
+        let parent = generate_random_block(&H256::default());
+        let mut block = generate_random_block(&parent.hash());
+
+        assert!(matches!(second.seal(&mut block.header, 3), Seal::NotOurTurn));
+        assert!(matches!(observer.seal(&mut block.header, 1), Seal::NotOurTurn));
+        assert!(block.header.seal.is_empty());
+        assert!(matches!(second.seal(&mut block.header, 4), Seal::Sealed));
+
+        assert_eq!(observer.verify_seal(&block.header, &parent.header, 4), Ok(()));
+        assert_eq!(observer.verify_seal(&block.header, &parent.header, 1), Ok(()));
+        assert_eq!(observer.verify_seal(&block.header, &parent.header, 5), Err(SealError::BadSignature));
+        assert!(observer.plausible_seal(&block.header));
+
+        // the seal covers every other field of the header
+        block.header.nonce = block.header.nonce.wrapping_add(1);
+        assert_eq!(observer.verify_seal(&block.header, &parent.header, 4), Err(SealError::BadSignature));
+        assert!(!observer.plausible_seal(&block.header));
+        assert_eq!(observer.weight(&block.header), H256::from(1u64));
+    }
+}
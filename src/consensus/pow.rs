@@ -0,0 +1,78 @@
+use super::{Consensus, Seal, SealError};
+use crate::types::block::Header;
+use crate::types::hash::{H256, Hashable};
+
+/// Proof of work: the hash of a header must be at most its difficulty, which never changes
+pub struct Pow;
+
+/// Whether the hash of `header` is at most its difficulty
+pub fn meets_target(header: &Header) -> bool {
+    header.hash() <= header.difficulty
+}
+
+impl Consensus for Pow {
+    fn name(&self) -> &'static str {
+        "pow"
+    }
+
+    fn seal(&self, _header: &mut Header, _height: u64) -> Seal {
+        Seal::Search
+    }
+
+    fn verify_seal(&self, header: &Header, parent: &Header, _height: u64) -> Result<(), SealError> {
+        if !header.seal.is_empty() {
+            return Err(SealError::UnexpectedSeal);
+        }
+        if header.difficulty != parent.difficulty {
+            return Err(SealError::DifficultyChanged);
+        }
+        if !meets_target(header) {
+            return Err(SealError::TargetMissed);
+        }
+        Ok(())
+    }
+
+    fn plausible_seal(&self, header: &Header) -> bool {
+        header.seal.is_empty() && meets_target(header)
+    }
+
+    /// Expected number of hashes to find the block, so the fork choice follows the most work
+    fn weight(&self, header: &Header) -> H256 {
+        header.difficulty.work()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::block::generate_random_block;
+
+    #[test]
+    fn verify_seal() {
+        let parent = generate_random_block(&H256::default());
+        let mut block = generate_random_block(&parent.hash());
+        assert_eq!(Pow.verify_seal(&block.header, &parent.header, 1), Ok(()));
+        assert!(Pow.plausible_seal(&block.header));
+
+        block.header.seal = vec![1];
+        assert_eq!(Pow.verify_seal(&block.header, &parent.header, 1), Err(SealError::UnexpectedSeal));
+        block.header.seal.clear();
+
+        block.header.difficulty = H256::default();
+        assert_eq!(Pow.verify_seal(&block.header, &parent.header, 1), Err(SealError::DifficultyChanged));
+        let mut parent = parent;
+        parent.header.difficulty = H256::default();
+        assert_eq!(Pow.verify_seal(&block.header, &parent.header, 1), Err(SealError::TargetMissed));
+        assert!(!Pow.plausible_seal(&block.header));
+
+        // halving the target doubles the work
+        let mut easy = [255u8; 32];
+        easy[0] = 0x7f;
+        block.header.difficulty = easy.into();
+        let mut hard = [255u8; 32];
+        hard[0] = 0x3f;
+        parent.header.difficulty = hard.into();
+        assert_eq!(Pow.weight(&block.header), H256::from(2u64));
+        assert_eq!(Pow.weight(&parent.header), H256::from(4u64));
+    }
+}
@@ -4,17 +4,22 @@ extern crate hex_literal;
 
 pub mod api;
 pub mod blockchain;
+pub mod consensus;
 pub mod types;
 pub mod miner;
 pub mod network;
 pub mod txgen;
+pub mod wallet;
 
-use blockchain::{Blockchain, Mempool};
+use blockchain::{Blockchain, Mempool, SignatureCache};
 use clap::clap_app;
+use consensus::{Consensus, Poa, Pow};
+use ring::signature::{Ed25519KeyPair, KeyPair};
 use smol::channel;
 use log::{error, info};
 use api::Server as ApiServer;
 use std::net;
+use std::path::Path;
 use std::process;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -30,18 +35,91 @@ fn main() {
      (@arg api_addr: --api [ADDR] default_value("127.0.0.1:7000") "Sets the IP address and the port of the API server")
      (@arg known_peer: -c --connect ... [PEER] "Sets the peers to connect to at start")
      (@arg p2p_workers: --("p2p-workers") [INT] default_value("4") "Sets the number of worker threads for P2P server")
+     (@arg miner_threads: --("miner-threads") [INT] default_value("1") "Sets the number of threads hashing block templates")
+     (@arg getwork_addr: --getwork [ADDR] "Serves block templates to external miners at this address")
+     (@arg consensus: --consensus [ENGINE] possible_value("pow") possible_value("poa") default_value("pow") "Sets the consensus engine: proof of work or proof of authority")
+     (@arg poa_signers: --("poa-signers") [KEYS] "Sets the proof of authority signers, as comma-separated hex Ed25519 public keys in turn order")
+     (@arg poa_key: --("poa-key") [SEED] "Seals proof of authority blocks with the Ed25519 key of this hex 32-byte seed")
+     (@arg regtest: --regtest "Runs a local test network with a trivial difficulty, where /miner/generate mines blocks on demand")
+     (@arg chain_id: --("chain-id") [INT] default_value("0") "Sets the chain id that transactions must be signed for")
+     (@arg keystore: --keystore [DIR] "Loads signing keys from an encrypted keystore directory")
+     (@arg passphrase: --passphrase [PASS] "Sets the keystore passphrase (defaults to the WALLET_PASSPHRASE environment variable)")
+     (@arg wallet_keys: --("wallet-keys") [INT] default_value("3") "Sets the number of keys to create in an empty keystore, or to derive from a mnemonic")
+     (@arg mnemonic: --mnemonic [WORDS] "Derives signing keys from a mnemonic seed backup (defaults to the WALLET_MNEMONIC environment variable)")
+     (@arg new_mnemonic: --("new-mnemonic") "Prints a new random mnemonic seed backup and exits")
     )
     .get_matches();
 
+    // only print a mnemonic, before anything binds a port
+    if matches.is_present("new_mnemonic") {
+        println!("{}", wallet::hd::to_mnemonic(&wallet::hd::random_seed()));
+        return;
+    }
+
     // init logger
     let verbosity = matches.occurrences_of("verbose") as usize;
     stderrlog::new().verbosity(verbosity).init().unwrap();
-    let blockchain = Blockchain::new();
+
+    // parse chain id
+    let chain_id = matches
+        .value_of("chain_id")
+        .unwrap()
+        .parse::<u32>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing chain id: {}", e);
+            process::exit(1);
+        });
+
+    // pick the consensus engine
+    let consensus: Arc<dyn Consensus> = match matches.value_of("consensus").unwrap() {
+        "poa" => {
+            let signers = matches.value_of("poa_signers").unwrap_or_else(|| {
+                error!("Proof of authority needs --poa-signers");
+                process::exit(1);
+            });
+            let signers: Vec<Vec<u8>> = signers
+                .split(',')
+                .map(|signer| match hex::decode(signer.trim()) {
+                    Ok(key) if key.len() == 32 => key,
+                    _ => {
+                        error!("Error parsing signer {}: expected 32 hex-encoded bytes", signer);
+                        process::exit(1);
+                    }
+                })
+                .collect();
+            let key = matches.value_of("poa_key").map(|seed| {
+                let key = hex::decode(seed)
+                    .ok()
+                    .and_then(|seed| Ed25519KeyPair::from_seed_unchecked(&seed).ok())
+                    .unwrap_or_else(|| {
+                        error!("Error parsing proof of authority key: expected a 32-byte hex seed");
+                        process::exit(1);
+                    });
+                if !signers.iter().any(|signer| signer.as_slice() == key.public_key().as_ref()) {
+                    error!("The proof of authority key is not one of the signers");
+                    process::exit(1);
+                }
+                key
+            });
+            Arc::new(Poa::new(signers, key))
+        }
+        _ => Arc::new(Pow),
+    };
+    info!("Using {} consensus", consensus.name());
+    let blockchain = if matches.is_present("regtest") {
+        info!("Running in regtest mode");
+        Blockchain::new_regtest(chain_id, consensus)
+    } else {
+        Blockchain::new_with_consensus(chain_id, consensus)
+    };
     let blockchain = Arc::new(Mutex::new(blockchain));
 
     let mempool = Mempool::new();
     let mempool = Arc::new(Mutex::new(mempool));
 
+    let sig_cache = SignatureCache::new(100000);
+    let sig_cache = Arc::new(Mutex::new(sig_cache));
+
     // parse p2p server address
     let p2p_addr = matches
         .value_of("peer_addr")
@@ -84,20 +162,93 @@ fn main() {
         &server,
         &blockchain,
         &mempool,
-        
+        &sig_cache,
     );
     worker_ctx.start();
 
     // start the miner
-    let (miner_ctx, miner, finished_block_chan) = miner::new(&blockchain,&mempool);
+    let miner_threads = matches
+        .value_of("miner_threads")
+        .unwrap()
+        .parse::<usize>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing miner threads: {}", e);
+            process::exit(1);
+        });
+    let (miner_ctx, miner, finished_block_chan) = miner::new(&blockchain,&mempool, miner_threads);
 
     // new lines:
     let miner_worker_ctx = miner::worker::Worker::new(&server, finished_block_chan,&blockchain); // let miner_worker_ctx = miner::worker::Worker::new(&server, finished_block_chan);
     miner_ctx.start();
     miner_worker_ctx.start();
 
+    // start the getwork server for external miners
+    if let Some(addr) = matches.value_of("getwork_addr") {
+        let getwork_addr = addr.parse::<net::SocketAddr>().unwrap_or_else(|e| {
+            error!("Error parsing getwork server address: {}", e);
+            process::exit(1);
+        });
+        miner::getwork::Server::start(getwork_addr, &blockchain, &mempool, &server).unwrap_or_else(|e| {
+            error!("Error starting getwork server: {}", e);
+            process::exit(1);
+        });
+    }
+
+    let wallet_keys = matches
+        .value_of("wallet_keys")
+        .unwrap()
+        .parse::<u32>()
+        .unwrap_or_else(|e| {
+            error!("Error parsing wallet keys: {}", e);
+            process::exit(1);
+        });
+
+    // open the keystore
+    let wallet = matches.value_of("keystore").map(|dir| {
+        let passphrase = match matches.value_of("passphrase") {
+            Some(v) => v.to_string(),
+            None => std::env::var("WALLET_PASSPHRASE").unwrap_or_else(|_| {
+                error!("A keystore needs --passphrase or WALLET_PASSPHRASE");
+                process::exit(1);
+            }),
+        };
+        let mut wallet = wallet::Wallet::open(Path::new(dir), &passphrase).unwrap_or_else(|e| {
+            error!("Error opening keystore: {}", e);
+            process::exit(1);
+        });
+        if wallet.addresses().is_empty() {
+            for _ in 0..wallet_keys {
+                let address = wallet.generate().unwrap_or_else(|e| {
+                    error!("Error creating key: {}", e);
+                    process::exit(1);
+                });
+                info!("Created key for address {}", address);
+            }
+        }
+        wallet
+    });
+
+    // or derive the keys from a mnemonic
+    let mnemonic = matches
+        .value_of("mnemonic")
+        .map(|v| v.to_string())
+        .or_else(|| std::env::var("WALLET_MNEMONIC").ok());
+    let hd_wallet = mnemonic.map(|words| {
+        let seed = wallet::hd::from_mnemonic(&words).unwrap_or_else(|e| {
+            error!("Error parsing mnemonic: {}", e);
+            process::exit(1);
+        });
+        wallet::hd::HdWallet::from_seed(&seed)
+    });
+
+    let signing_keys = match (&wallet, &hd_wallet) {
+        (Some(wallet), _) => Some(wallet.key_pairs()),
+        (None, Some(hd_wallet)) => Some((0..wallet_keys).map(|i| hd_wallet.key_pair(i)).collect()),
+        (None, None) => None,
+    };
+
     // start the generator
-    let (generator_ctx, generator, finished_tx_chan) = txgen::new(&mempool,&blockchain, p2p_addr);
+    let (generator_ctx, generator, finished_tx_chan) = txgen::new(&mempool,&blockchain, p2p_addr, signing_keys);
 
     // new lines:
     let generator_worker_ctx = txgen::worker::Worker::new(&server, finished_tx_chan,&mempool); // let miner_worker_ctx = miner::worker::Worker::new(&server, finished_block_chan);
@@ -146,6 +297,7 @@ fn main() {
         &generator,
         &server,
         &blockchain,
+        &mempool,
     );
 
     loop {
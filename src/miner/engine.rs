@@ -0,0 +1,288 @@
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use log::info;
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time;
+
+use crate::consensus::pow::meets_target;
+use crate::types::block::{Block, Header};
+
+/// A block to mine. Hashing threads only change the nonce of its header.
+pub struct Template {
+    /// generation number of the engine when the template was installed
+    pub id: u64,
+    pub block: Block,
+}
+
+struct Shared {
+    // current template (None while idle) and the condition variable hashing threads wait on
+    job: Mutex<Option<Arc<Template>>>,
+    job_changed: Condvar,
+    // bumped on every template change; hashing threads stop as soon as it differs from their template id
+    generation: AtomicU64,
+    // microseconds to sleep between hashes, as the lambda of the miner
+    lambda: AtomicU64,
+    // next unused extra nonce; each value is handed out once for the life of the engine
+    next_extra_nonce: AtomicU64,
+    // headers hashed so far by all threads, for the hash rate
+    hashes: AtomicU64,
+    shutdown: AtomicBool,
+}
+
+/// Hashes one block template on several threads, each covering its own slice of the nonce space.
+pub struct Engine {
+    shared: Arc<Shared>,
+    found: Receiver<(u64, Block)>,
+}
+
+/// Inclusive nonce range searched by thread `index` of `threads`
+pub fn nonce_range(index: usize, threads: usize) -> (u32, u32) {
+    split_nonces(index, threads, u32::MAX)
+}
+
+/// Inclusive range of thread `index` of `threads` when the nonces go from 0 to `last_nonce`
+fn split_nonces(index: usize, threads: usize, last_nonce: u32) -> (u32, u32) {
+    let span = (last_nonce as u64 + 1) / threads as u64;
+    let start = index as u64 * span;
+    let end = if index + 1 == threads { last_nonce as u64 } else { start + span - 1 };
+    (start as u32, end as u32)
+}
+
+impl Engine {
+    pub fn start(threads: usize) -> Self {
+        Self::start_with_last_nonce(threads, u32::MAX)
+    }
+
+    /// Start an engine that only tries nonces up to `last_nonce`, so tests can exhaust them
+    fn start_with_last_nonce(threads: usize, last_nonce: u32) -> Self {
+        let threads = threads.max(1);
+        let shared = Arc::new(Shared {
+            job: Mutex::new(None),
+            job_changed: Condvar::new(),
+            generation: AtomicU64::new(0),
+            lambda: AtomicU64::new(0),
+            next_extra_nonce: AtomicU64::new(0),
+            hashes: AtomicU64::new(0),
+            shutdown: AtomicBool::new(false),
+        });
+        let (found_sender, found_receiver) = unbounded();
+        for index in 0..threads {
+            let shared = Arc::clone(&shared);
+            let found = found_sender.clone();
+            thread::Builder::new()
+                .name(format!("miner-hash-{}", index))
+                .spawn(move || {
+                    hash_loop(split_nonces(index, threads, last_nonce), last_nonce, &shared, &found);
+                })
+                .unwrap();
+        }
+        info!("Mining engine started with {} threads", threads);
+        Engine { shared, found: found_receiver }
+    }
+
+    /// Replace the block being mined, interrupting every hashing thread. `None` idles the threads.
+    /// The template gets a fresh extra nonce. Returns the id of the new template.
+    pub fn set_template(&self, block: Option<Block>) -> u64 {
+        let mut job = self.shared.job.lock().unwrap();
+        let id = self.shared.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        *job = block.map(|mut block| {
+            block.header.extra_nonce = self.shared.next_extra_nonce.fetch_add(1, Ordering::SeqCst);
+            Arc::new(Template { id, block })
+        });
+        self.shared.job_changed.notify_all();
+        id
+    }
+
+    /// Id of the current template
+    pub fn current_id(&self) -> u64 {
+        self.shared.generation.load(Ordering::SeqCst)
+    }
+
+    pub fn set_lambda(&self, lambda: u64) {
+        self.shared.lambda.store(lambda, Ordering::Relaxed);
+    }
+
+    /// Number of headers hashed since the engine started
+    pub fn hashes(&self) -> u64 {
+        self.shared.hashes.load(Ordering::Relaxed)
+    }
+
+    /// Blocks found by the hashing threads, with the id of the template they were mined from
+    pub fn found(&self) -> &Receiver<(u64, Block)> {
+        &self.found
+    }
+
+    pub fn shutdown(&self) {
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        self.set_template(None);
+    }
+}
+
+/// Search the templates of `shared` over `range`, then over every nonce up to `last_nonce` for each fresh extra nonce
+fn hash_loop(range: (u32, u32), last_nonce: u32, shared: &Shared, found: &Sender<(u64, Block)>) {
+    let mut last_id = 0;
+    loop {
+        // wait for a template this thread has not searched yet
+        let template = {
+            let mut job = shared.job.lock().unwrap();
+            loop {
+                if shared.shutdown.load(Ordering::SeqCst) {
+                    return;
+                }
+                match job.as_ref() {
+                    Some(template) if template.id != last_id => break Arc::clone(template),
+                    _ => job = shared.job_changed.wait(job).unwrap(),
+                }
+            }
+        };
+        last_id = template.id;
+
+        // the template's extra nonce is shared, each thread searching its own nonce range; once that
+        // is exhausted the thread takes an unused extra nonce of its own and searches every nonce
+        let mut header = template.block.header.clone();
+        let mut nonces = range;
+        while let SearchResult::Exhausted = search(&mut header, nonces, &template, shared, found) {
+            header.extra_nonce = shared.next_extra_nonce.fetch_add(1, Ordering::SeqCst);
+            nonces = (0, last_nonce);
+        }
+    }
+}
+
+/// Hashes counted by a thread before adding them to the shared counter
+const HASH_BATCH: u64 = 1024;
+
+enum SearchResult {
+    Found,
+    Interrupted,
+    Exhausted,
+}
+
+/// Try every nonce of `range` (inclusive) in `header`, sending a block if one meets the proof of work target
+fn search(header: &mut Header, range: (u32, u32), template: &Template, shared: &Shared, found: &Sender<(u64, Block)>) -> SearchResult {
+    // hashes not yet added to the shared counter
+    let mut hashes: u64 = 0;
+    let result = 'search: {
+        for nonce in range.0..=range.1 {
+            if shared.generation.load(Ordering::Relaxed) != template.id {
+                break 'search SearchResult::Interrupted;
+            }
+            header.nonce = nonce;
+            hashes += 1;
+            if hashes == HASH_BATCH {
+                shared.hashes.fetch_add(hashes, Ordering::Relaxed);
+                hashes = 0;
+            }
+            if meets_target(header) {
+                let block = Block { header: header.clone(), content: template.block.content.clone() };
+                let _ = found.send((template.id, block));
+                break 'search SearchResult::Found;
+            }
+            let lambda = shared.lambda.load(Ordering::Relaxed);
+            if lambda != 0 {
+                thread::sleep(time::Duration::from_micros(lambda));
+            }
+        }
+        SearchResult::Exhausted
+    };
+    shared.hashes.fetch_add(hashes, Ordering::Relaxed);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::block::generate_random_block;
+    use crate::types::hash::{H256, Hashable};
+
+    #[test]
+    fn nonce_ranges_cover_the_nonce_space() {
+        for threads in 1..8 {
+            let mut next: u64 = 0;
+            for index in 0..threads {
+                let (start, end) = nonce_range(index, threads);
+                assert_eq!(start as u64, next);
+                next = end as u64 + 1;
+            }
+            assert_eq!(next, u32::MAX as u64 + 1);
+        }
+    }
+
+    #[test]
+    fn engine_finds_block() {
+        let engine = Engine::start(3);
+        let mut block = generate_random_block(&H256::default());
+        // one hash in 16 on average
+        let mut difficulty = [255u8; 32];
+        difficulty[0] = 0x0f;
+        block.header.difficulty = difficulty.into();
+        let id = engine.set_template(Some(block.clone()));
+        let (found_id, found) = engine.found().recv_timeout(time::Duration::from_secs(10)).unwrap();
+        assert_eq!(found_id, id);
+        assert!(found.hash() <= found.header.difficulty);
+        assert_eq!(found.get_parent(), block.get_parent());
+        engine.shutdown();
+    }
+
+    #[test]
+    fn extra_nonce_rolling() {
+        let engine = Engine::start(1);
+        let block = generate_random_block(&H256::default());
+        // an impossible target keeps the thread searching; every template gets a new extra nonce
+        let mut impossible = block.clone();
+        impossible.header.difficulty = H256::default();
+        let first = engine.set_template(Some(impossible.clone()));
+        let second = engine.set_template(Some(impossible.clone()));
+        assert_ne!(first, second);
+        assert!(engine.shared.next_extra_nonce.load(Ordering::SeqCst) >= 2);
+        engine.shutdown();
+
+        // searching a range either finds a block or reports the range exhausted
+        let template = Template { id: engine.current_id(), block: impossible };
+        let (sender, receiver) = unbounded();
+        let mut header = template.block.header.clone();
+        assert!(matches!(search(&mut header, (0, 15), &template, &engine.shared, &sender), SearchResult::Exhausted));
+        assert_eq!(header.nonce, 15);
+        assert!(receiver.try_recv().is_err());
+        assert!(engine.hashes() >= 16);
+        header.difficulty = H256::from([255u8; 32]);
+        assert!(matches!(search(&mut header, (7, 15), &template, &engine.shared, &sender), SearchResult::Found));
+        assert_eq!(receiver.try_recv().unwrap().1.header.nonce, 7);
+    }
+
+    #[test]
+    fn exhausted_nonces_roll_the_extra_nonce() {
+        // 16 nonces per extra nonce and one hash in 64 meeting the target: find a template whose first
+        // solution, in search order, needs at least one rollover
+        let mut difficulty = [255u8; 32];
+        difficulty[0] = 0x03;
+        let (block, solution) = loop {
+            let mut block = generate_random_block(&H256::default());
+            block.header.difficulty = difficulty.into();
+            let mut header = block.header.clone();
+            let first = (0..8u64).flat_map(|extra_nonce| (0..16u32).map(move |nonce| (extra_nonce, nonce))).find(|(extra_nonce, nonce)| {
+                header.extra_nonce = *extra_nonce;
+                header.nonce = *nonce;
+                meets_target(&header)
+            });
+            match first {
+                Some(solution) if solution.0 > 0 => break (block, solution),
+                _ => continue,
+            }
+        };
+        let engine = Engine::start_with_last_nonce(1, 15);
+        let id = engine.set_template(Some(block));
+        let (found_id, found) = engine.found().recv_timeout(time::Duration::from_secs(10)).unwrap();
+        assert_eq!(found_id, id);
+        assert_eq!((found.header.extra_nonce, found.header.nonce), solution);
+        // every header before the solution was hashed exactly once
+        let expected = solution.0 * 16 + solution.1 as u64 + 1;
+        let deadline = time::Instant::now() + time::Duration::from_secs(10);
+        while engine.hashes() < expected && time::Instant::now() < deadline {
+            thread::sleep(time::Duration::from_millis(1));
+        }
+        assert_eq!(engine.hashes(), expected);
+        engine.shutdown();
+    }
+}
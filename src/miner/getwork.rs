@@ -0,0 +1,253 @@
+//! Mining interface for external miners, one JSON object per line over TCP.
+//!
+//! - `{"method":"getwork"}` returns a block template as
+//!   `{"id":N,"header":"<hex>","nonce_offset":36,"target":"<hex>"}`, where `header` is the canonical
+//!   encoding of the header. The miner changes the 4 big endian bytes at `nonce_offset` until the
+//!   SHA256 of the header is at most `target`, both compared as 32 big endian bytes.
+//! - `{"method":"submit","id":N,"nonce":n}` hands back a solved template, answered with
+//!   `{"accepted":true,"block":"<hash>"}` or `{"accepted":false,"error":"<reason>"}`.
+//!
+//! Templates are only built from mempool transactions, like the built-in miner does.
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::blockchain::{Blockchain, Mempool};
+use crate::consensus::Seal;
+use crate::network::message::Message;
+use crate::network::server::Handle as NetworkServerHandle;
+use crate::types::block::{generate_block, Block, NONCE_OFFSET};
+use crate::types::encoding::Encode;
+use crate::types::hash::Hashable;
+
+use super::{prune_mempool, select_transactions};
+
+/// Number of issued templates kept for submission; older ones are forgotten
+const MAX_TEMPLATES: usize = 64;
+
+/// First extra nonce handed to external miners, far from the values used by the built-in miner
+const FIRST_EXTRA_NONCE: u64 = 1 << 63;
+
+#[derive(Deserialize)]
+#[serde(tag = "method", rename_all = "lowercase")]
+enum Request {
+    Getwork,
+    Submit { id: u64, nonce: u32 },
+}
+
+#[derive(Serialize)]
+struct Work {
+    id: u64,
+    header: String,
+    nonce_offset: usize,
+    target: String,
+}
+
+#[derive(Serialize)]
+struct SubmitResult {
+    accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    block: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+/// Templates handed out to external miners, by id
+struct Templates {
+    next_id: u64,
+    next_extra_nonce: u64,
+    blocks: HashMap<u64, Block>,
+    order: VecDeque<u64>,
+}
+
+impl Templates {
+    fn new() -> Self {
+        Templates {
+            next_id: 1,
+            next_extra_nonce: FIRST_EXTRA_NONCE,
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Store `block` with an unused extra nonce, returning its id and the stored block
+    fn issue(&mut self, mut block: Block) -> (u64, Block) {
+        let id = self.next_id;
+        self.next_id += 1;
+        block.header.extra_nonce = self.next_extra_nonce;
+        self.next_extra_nonce += 1;
+        if self.order.len() == MAX_TEMPLATES {
+            if let Some(old) = self.order.pop_front() {
+                self.blocks.remove(&old);
+            }
+        }
+        self.order.push_back(id);
+        self.blocks.insert(id, block.clone());
+        (id, block)
+    }
+}
+
+pub struct Server {
+    blockchain: Arc<Mutex<Blockchain>>,
+    mempool: Arc<Mutex<Mempool>>,
+    network: NetworkServerHandle,
+    templates: Mutex<Templates>,
+}
+
+impl Server {
+    pub fn start(
+        addr: SocketAddr,
+        blockchain: &Arc<Mutex<Blockchain>>,
+        mempool: &Arc<Mutex<Mempool>>,
+        network: &NetworkServerHandle,
+    ) -> std::io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        let server = Arc::new(Self {
+            blockchain: Arc::clone(blockchain),
+            mempool: Arc::clone(mempool),
+            network: network.clone(),
+            templates: Mutex::new(Templates::new()),
+        });
+        thread::Builder::new()
+            .name("getwork-listener".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            warn!("Error accepting external miner: {}", e);
+                            continue;
+                        }
+                    };
+                    let server = Arc::clone(&server);
+                    thread::spawn(move || {
+                        if let Err(e) = server.serve(stream) {
+                            debug!("External miner disconnected: {}", e);
+                        }
+                    });
+                }
+            })
+            .unwrap();
+        info!("Getwork server listening at {}", &addr);
+        Ok(())
+    }
+
+    fn serve(&self, stream: TcpStream) -> std::io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response = match serde_json::from_str::<Request>(&line) {
+                Ok(Request::Getwork) => self.getwork(),
+                Ok(Request::Submit { id, nonce }) => serde_json::to_string(&self.submit(id, nonce)).unwrap(),
+                Err(e) => serde_json::to_string(&ErrorResponse { error: e.to_string() }).unwrap(),
+            };
+            writer.write_all(response.as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn getwork(&self) -> String {
+        let block = {
+            // lock order: mempool before blockchain, as in the network worker
+            let mut mempool = self.mempool.lock().unwrap();
+            let blockchain = self.blockchain.lock().unwrap();
+            let tip = blockchain.tip();
+            prune_mempool(&blockchain, &mut mempool, &tip);
+            let block_transactions = select_transactions(&blockchain, &mempool, &tip);
+            if block_transactions.is_empty() {
+                Err("no transactions to mine")
+            } else {
+                let mut block = generate_block(&tip, &blockchain.map[&tip].get_difficulty(), &block_transactions);
+                match blockchain.consensus.seal(&mut block.header, blockchain.level_map[&tip] + 1) {
+                    Seal::Search => Ok(block),
+                    _ => Err("the consensus engine does not use proof of work"),
+                }
+            }
+        };
+        let block = match block {
+            Ok(block) => block,
+            Err(error) => {
+                return serde_json::to_string(&ErrorResponse { error: error.to_string() }).unwrap();
+            }
+        };
+        let (id, block) = self.templates.lock().unwrap().issue(block);
+        let work = Work {
+            id,
+            header: hex::encode(block.header.encoded()),
+            nonce_offset: NONCE_OFFSET,
+            target: block.header.difficulty.to_string(),
+        };
+        serde_json::to_string(&work).unwrap()
+    }
+
+    fn submit(&self, id: u64, nonce: u32) -> SubmitResult {
+        let rejected = |error: &str| SubmitResult { accepted: false, block: None, error: Some(error.to_string()) };
+        let mut block = match self.templates.lock().unwrap().blocks.get(&id) {
+            Some(block) => block.clone(),
+            None => return rejected("unknown or expired work id"),
+        };
+        block.header.nonce = nonce;
+        let hash = block.hash();
+        {
+            let mut blockchain = self.blockchain.lock().unwrap();
+            if blockchain.map.contains_key(&hash) {
+                return rejected("duplicate block");
+            }
+            let (parent, parent_level) = match (blockchain.map.get(&block.get_parent()), blockchain.level_map.get(&block.get_parent())) {
+                (Some(parent), Some(level)) => (parent, *level),
+                _ => return rejected("unknown parent"),
+            };
+            if let Err(e) = blockchain.consensus.verify_seal(&block.header, &parent.header, parent_level + 1) {
+                return rejected(&e.to_string());
+            }
+            blockchain.insert(&block);
+        }
+        info!("External miner found block {}", hash);
+        self.network.broadcast(Message::NewBlockHashes(vec![hash]));
+        SubmitResult { accepted: true, block: Some(hash.to_string()), error: None }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::block::generate_random_block;
+    use crate::types::hash::H256;
+
+    #[test]
+    fn nonce_offset() {
+        let mut block = generate_random_block(&H256::default());
+        block.header.nonce = 0x01020304;
+        let encoded = block.header.encoded();
+        assert_eq!(&encoded[NONCE_OFFSET..NONCE_OFFSET + 4], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn templates_expire() {
+        let mut templates = Templates::new();
+        let block = generate_random_block(&H256::default());
+        let (first, issued) = templates.issue(block.clone());
+        assert_eq!(issued.header.extra_nonce, FIRST_EXTRA_NONCE);
+        for _ in 0..MAX_TEMPLATES {
+            let (_, issued) = templates.issue(block.clone());
+            assert!(issued.header.extra_nonce > FIRST_EXTRA_NONCE);
+        }
+        assert!(!templates.blocks.contains_key(&first));
+        assert_eq!(templates.blocks.len(), MAX_TEMPLATES);
+    }
+}
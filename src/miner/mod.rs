@@ -1,9 +1,11 @@
+pub mod engine;
+pub mod getwork;
+pub mod strategy;
 pub mod worker;
 
 use log::info;
 
-use crossbeam::channel::{unbounded, Receiver, Sender, TryRecvError};
-use std::mem;
+use crossbeam::channel::{select, unbounded, Receiver, Sender};
 use std::time;
 
 use std::thread;
@@ -17,15 +19,23 @@ use std::sync::{Arc, Mutex};
 use crate::blockchain::Blockchain;
 use crate::types::hash::{H256, Hashable};
 
-use crate::types::block::{generate_block};
+use crate::types::block::{generate_block, Content, MAX_BLOCK_SIZE};
 
-use std::collections::HashMap;
 use crate::types::address::Address;
 
+use crate::consensus::pow::meets_target;
+use crate::consensus::Seal;
+
+use self::engine::Engine;
+use self::strategy::{PrivateChain, Strategy};
+
+/// How often a running miner looks for a new tip or new transactions to mine
+const TEMPLATE_POLL: time::Duration = time::Duration::from_millis(10);
 
 enum ControlSignal {
-    Start(u64), // the number controls the lambda of interval between block generation
+    Start(u64, Strategy), // the number controls the lambda of interval between block generation
     Update, // update the block in mining, it may due to new blockchain tip or new transaction
+    Pause,
     Exit,
 }
 
@@ -35,6 +45,52 @@ enum OperatingState {
     ShutDown,
 }
 
+impl OperatingState {
+    fn name(&self) -> &'static str {
+        match self {
+            OperatingState::Paused => "paused",
+            OperatingState::Run(_) => "running",
+            OperatingState::ShutDown => "shut down",
+        }
+    }
+}
+
+/// How often the hash rate in the status is recomputed
+const HASHRATE_INTERVAL: time::Duration = time::Duration::from_secs(1);
+
+/// Snapshot of what the miner is doing, kept up to date by the miner thread
+#[derive(Clone)]
+pub struct Status {
+    pub state: &'static str,
+    pub lambda: Option<u64>,
+    pub strategy: &'static str,
+    /// blocks mined but not published yet by a withholding strategy
+    pub withheld_blocks: usize,
+    /// headers hashed per second over the last interval
+    pub hashrate: f64,
+    pub blocks_mined: u64,
+    /// mined blocks that did not extend the tip, because the template was replaced or a new tip arrived
+    /// first, or that a withholding strategy gave up
+    pub stale_blocks: u64,
+    /// parent and transaction count of the template being mined
+    pub template: Option<(H256, usize)>,
+}
+
+impl Status {
+    fn new() -> Self {
+        Status {
+            state: OperatingState::Paused.name(),
+            lambda: None,
+            strategy: Strategy::Honest.name(),
+            withheld_blocks: 0,
+            hashrate: 0.0,
+            blocks_mined: 0,
+            stale_blocks: 0,
+            template: None,
+        }
+    }
+}
+
 pub struct Context {
     /// Channel for receiving control signal
     control_chan: Receiver<ControlSignal>,
@@ -42,29 +98,37 @@ pub struct Context {
     finished_block_chan: Sender<Block>,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
+    // number of hashing threads
+    threads: usize,
+    status: Arc<Mutex<Status>>,
 }
 
 #[derive(Clone)]
 pub struct Handle {
     /// Channel for sending signal to the miner thread
     control_chan: Sender<ControlSignal>,
+    status: Arc<Mutex<Status>>,
 }
 
-pub fn new(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>) -> (Context, Handle, Receiver<Block>) { // should blockchain and mp have & infront here?
+pub fn new(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>, threads: usize) -> (Context, Handle, Receiver<Block>) { // should blockchain and mp have & infront here?
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
     let (finished_block_sender, finished_block_receiver) = unbounded();
     let blockchain_clone = Arc::clone(blockchain); // note arc::clone is just creating another reference to same thing
     let mempool_clone = Arc::clone(mempool);
+    let status = Arc::new(Mutex::new(Status::new()));
     let ctx = Context {
         control_chan: signal_chan_receiver,
         operating_state: OperatingState::Paused,
         finished_block_chan: finished_block_sender,
         blockchain: blockchain_clone, // am I allowed to have two variables with the same name like this?
         mempool: mempool_clone,
+        threads,
+        status: Arc::clone(&status),
     };
 
     let handle = Handle {
         control_chan: signal_chan_sender,
+        status,
     };
 
     (ctx, handle, finished_block_receiver)
@@ -76,22 +140,77 @@ fn test_new() -> (Context, Handle, Receiver<Block>) {
     let new_mp = Mempool::new();
     let wrapped_bc = Arc::new(Mutex::new(new_bc));
     let wrapped_mp = Arc::new(Mutex::new(new_mp));
-    new(&wrapped_bc,&wrapped_mp)
+    new(&wrapped_bc,&wrapped_mp, 1)
+}
+
+/// Pick the mempool transactions to mine on top of `parent`: at most one per sender, each with the
+/// sender's next account nonce and enough balance, until the transaction count or block size limit is reached.
+pub fn select_transactions(blockchain: &Blockchain, mempool: &Mempool, parent: &H256) -> Vec<SignedTransaction> {
+    let max_transaction_count = 30; // number of transactions per block:
+    let block_state = blockchain.state_map.get(parent).unwrap();
+    let mut block_transactions: Vec<SignedTransaction> = Vec::new();
+    let mut used_addresses: Vec<Address> = Vec::new();
+    let mut block_size = bincode::serialized_size(&Content(Vec::new())).unwrap();
+
+    for value in mempool.map.values(){
+        let transaction = value.get_transaction();
+        let sendery = transaction.get_sender();
+        let valuey = match transaction.get_total_value() {
+            Some(v) => v,
+            None => continue,
+        };
+        let acny = transaction.get_account_nonce();
+        let tx_size = bincode::serialized_size(value).unwrap();
+        // time-locked transactions wait in the mempool until they unlock
+        if !blockchain.is_unlocked(&transaction, parent) || !blockchain.htlc_allowed(&transaction, parent) {
+            continue;
+        }
+
+        if let Some((send_an, send_bal)) = block_state.get(&sendery) {
+            if acny == send_an + 1 && valuey <= *send_bal && !used_addresses.contains(&sendery) && block_size + tx_size <= MAX_BLOCK_SIZE {
+                block_transactions.push(value.clone());
+                used_addresses.push(sendery);
+                block_size += tx_size;
+                if block_transactions.len() >= max_transaction_count{
+                    break;
+                }
+            }
+        }
+    }
+    block_transactions
 }
 
 impl Handle {
+    /// Stop the miner for good; it can not be started again
     pub fn exit(&self) {
-        self.control_chan.send(ControlSignal::Exit).unwrap();
+        // the miner thread is gone once it has shut down
+        let _ = self.control_chan.send(ControlSignal::Exit);
+    }
+
+    /// Returns false if the miner has been stopped
+    pub fn start(&self, lambda: u64) -> bool {
+        self.start_with_strategy(lambda, Strategy::Honest)
     }
 
-    pub fn start(&self, lambda: u64) {
+    /// Returns false if the miner has been stopped
+    pub fn start_with_strategy(&self, lambda: u64, strategy: Strategy) -> bool {
         self.control_chan
-            .send(ControlSignal::Start(lambda))
-            .unwrap();
+            .send(ControlSignal::Start(lambda, strategy))
+            .is_ok()
+    }
+
+    /// Returns false if the miner has been stopped
+    pub fn pause(&self) -> bool {
+        self.control_chan.send(ControlSignal::Pause).is_ok()
     }
 
     pub fn update(&self) {
-        self.control_chan.send(ControlSignal::Update).unwrap();
+        // nothing to update once the miner has been stopped
+        let _ = self.control_chan.send(ControlSignal::Update);
+    }
+
+    pub fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
     }
 }
 
@@ -107,230 +226,253 @@ impl Context {
     }
 
     fn miner_loop(&mut self) {
+        let engine = Engine::start(self.threads);
+        // parent and transactions of the template being mined, None to rebuild it
+        let mut template_key: Option<(H256, Vec<H256>)> = None;
+        let mut parent: H256 = self.blockchain.lock().unwrap().tip();
+        // hash count and time of the last hash rate update
+        let mut last_hashes = (engine.hashes(), time::Instant::now());
+        // branch of a withholding strategy, None when mining honestly
+        let mut private: Option<PrivateChain> = None;
 
-        // let mut c_blockchain = Arc::clone(&self.blockchain); 
-        let mut parent: H256;
-        {
-        parent = self.blockchain.lock().unwrap().tip();
-        }
         // main mining loop
-
-        // ***** Creating initial vec of transactions w block parameters to mine *****
-        
-        let max_transaction_count = 30; // number of transactions per block:
-        let mut cont = 0;
-        let mut block_transactions: Vec<SignedTransaction> = Vec::new();
-        let mut keys_to_remove: Vec<H256> = Vec::new();
-
-        
-
-        let mut flag = 0;
-
-
         loop {
-            let mut used_addresses: Vec<Address> = Vec::new();
-            if flag <=1 {
-                
-                {
-                    parent = self.blockchain.lock().unwrap().tip();
-                }
-
-                {
-                    cont =0;
-                    for (key, value) in self.mempool.lock().unwrap().map.clone().into_iter(){
-
-                        let mut block_state: HashMap<Address, (u32, u32)>;
-                        {
-                            block_state = self.blockchain.lock().unwrap().state_map.get(&parent.clone()).unwrap().clone();
-                        }
-
-                        let transaction = value.get_transaction();
-                        let sendery = transaction.get_sender();
-                        let valuey = transaction.get_value();
-                        let acny = transaction.get_account_nonce();
-
-                        
-                        if block_state.contains_key(&sendery){
-                            let (send_an, send_bal) = *block_state.get(&sendery).unwrap();
-                            if acny==(send_an+1) {
-                                if valuey <= send_bal{
-                                    if !used_addresses.contains(&sendery.clone()){
-                                        keys_to_remove.push(key.clone());
-                                        used_addresses.push(sendery.clone());
-                                        cont = cont+1;
-                                        if cont >= max_transaction_count{
-                                            break;
-                                        }
-                                    }
-                                    
-                                } 
-                            }
-                        }
-                        
-                    }
-                }
-                
-                for i in 0..keys_to_remove.len(){
-                    block_transactions.push(self.mempool.lock().unwrap().map.get(&keys_to_remove[i]).unwrap().clone());
-                }  
-                keys_to_remove.clear();
-
-                flag = flag+ 1;
-            }
             // check and react to control signals
-            match self.operating_state {
-                OperatingState::Paused => {
-                    let signal = self.control_chan.recv().unwrap();
-                    match signal {
-                        ControlSignal::Exit => {
-                            info!("Miner shutting down");
-                            self.operating_state = OperatingState::ShutDown;
-                        }
-                        ControlSignal::Start(i) => {
-                            info!("Miner starting in continuous mode with lambda {}", i);
-                            self.operating_state = OperatingState::Run(i);
-                        }
-                        ControlSignal::Update => {
-                            // in paused state, don't need to update
-                        }
-                    };
-                    continue;
-                }
+            let signal = match self.operating_state {
+                OperatingState::Paused => Some(self.control_chan.recv().unwrap()),
                 OperatingState::ShutDown => {
+                    engine.shutdown();
+                    self.update_status(|status| {
+                        status.state = OperatingState::ShutDown.name();
+                        status.lambda = None;
+                        status.hashrate = 0.0;
+                        status.template = None;
+                    });
                     return;
                 }
-                _ => match self.control_chan.try_recv() {
-                    Ok(signal) => {
-                        match signal {
-                            ControlSignal::Exit => {
-                                info!("Miner shutting down");
-                                self.operating_state = OperatingState::ShutDown;
-                            }
-                            ControlSignal::Start(i) => {
-                                info!("Miner starting in continuous mode with lambda {}", i);
-                                self.operating_state = OperatingState::Run(i);
-                            }
-                            ControlSignal::Update => {
-                                unimplemented!() // here I need to check what the new blockchain is and get the tip: How do I get the new blockchain?  
-                            }
-                        };
+                // wake up for control signals, blocks found by the engine, or to look for a new tip and transactions
+                OperatingState::Run(_) => select! {
+                    recv(self.control_chan) -> signal => Some(signal.expect("Miner control channel detached")),
+                    recv(engine.found()) -> found => {
+                        let (id, block) = found.unwrap();
+                        // blocks of a replaced template are stale: the tip or the transactions changed
+                        if id == engine.current_id() {
+                            self.publish(&block, &mut private);
+                            template_key = None;
+                        } else {
+                            self.count_mined(true);
+                        }
+                        None
                     }
-                    Err(TryRecvError::Empty) => {}
-                    Err(TryRecvError::Disconnected) => panic!("Miner control channel detached"),
+                    default(TEMPLATE_POLL) => None,
                 },
+            };
+            match signal {
+                Some(ControlSignal::Exit) => {
+                    info!("Miner shutting down");
+                    self.operating_state = OperatingState::ShutDown;
+                    continue;
+                }
+                Some(ControlSignal::Start(i, strategy)) => {
+                    info!("Miner starting in continuous mode with lambda {} and {} strategy", i, strategy.name());
+                    self.operating_state = OperatingState::Run(i);
+                    // blocks withheld by a previous strategy stay unpublished
+                    private = PrivateChain::new(strategy, &self.blockchain.lock().unwrap());
+                    self.update_status(|status| {
+                        status.strategy = strategy.name();
+                        status.withheld_blocks = 0;
+                    });
+                    template_key = None;
+                    last_hashes = (engine.hashes(), time::Instant::now());
+                }
+                Some(ControlSignal::Pause) => {
+                    info!("Miner paused");
+                    self.operating_state = OperatingState::Paused;
+                    engine.set_template(None);
+                    template_key = None;
+                    self.update_status(|status| {
+                        status.hashrate = 0.0;
+                        status.template = None;
+                    });
+                }
+                Some(ControlSignal::Update) => {
+                    // in paused state, don't need to update
+                    template_key = None;
+                }
+                None => {}
             }
-            if let OperatingState::ShutDown = self.operating_state {
-                return;
+            let state = &self.operating_state;
+            self.update_status(|status| {
+                status.state = state.name();
+                status.lambda = match state {
+                    OperatingState::Run(lambda) => Some(*lambda),
+                    _ => None,
+                };
+            });
+            match self.operating_state {
+                OperatingState::Run(lambda) => engine.set_lambda(lambda),
+                _ => continue,
             }
 
-            // TODO for student: actual mining, create a block
-            let mut dify: H256 = [255u8; 32].into();
-            
-            let c_parent = parent.clone();
-            let c1_parent = parent.clone();
-            // let d_blockchain = Arc::clone(&self.blockchain);
-            match self.blockchain.lock().unwrap().map.get(&c_parent){
-                Some(parent_block) => dify = parent_block.get_difficulty(), // May need to clone or dereference here
-                _=> println!("Invalid Parent Hash"),
+            let elapsed = last_hashes.1.elapsed();
+            if elapsed >= HASHRATE_INTERVAL {
+                let hashes = engine.hashes();
+                let hashrate = (hashes - last_hashes.0) as f64 / elapsed.as_secs_f64();
+                self.update_status(|status| status.hashrate = hashrate);
+                last_hashes = (hashes, time::Instant::now());
             }
-            let c_dify = dify.clone();
-
-            
-            let btclone = block_transactions.clone();
-
-            let new_block = generate_block(&c1_parent, &dify, &block_transactions); // Am I handling the merkle stuff right in new_block?
-            let block = new_block.clone();
-            
-            // TODO for student: if block mining finished, you can have something like: self.finished_block_chan.send(block.clone()).expect("Send finished block error");
-            if ((new_block.hash() <= c_dify) & !btclone.is_empty()){ 
-                self.finished_block_chan.send(block.clone()).expect("Send finished block error");
 
-                // this is duplicated is that a problem? (because miner worker also does this)
-                {
-                    self.blockchain.lock().unwrap().insert(&block);
+            // rebuild the template when the tip or the minable transactions change
+            let (tip, block_transactions) = {
+                // lock order: mempool before blockchain, as in the network worker
+                let mut mempool = self.mempool.lock().unwrap();
+                let mut blockchain = self.blockchain.lock().unwrap();
+                let tip = match private.as_mut() {
+                    Some(private) => {
+                        let (released, abandoned) = private.on_public(&blockchain);
+                        self.release(released, &mut blockchain);
+                        let withheld = private.withheld();
+                        self.update_status(|status| {
+                            status.stale_blocks += abandoned as u64;
+                            status.withheld_blocks = withheld;
+                        });
+                        private.tip()
+                    }
+                    None => blockchain.tip(),
+                };
+                if tip != parent {
+                    prune_mempool(&blockchain, &mut mempool, &tip);
+                    parent = tip;
                 }
-                println!("mined block");
-
-                
-            }
-            let mut temp_parent: H256;
-            {
-                temp_parent = self.blockchain.lock().unwrap().tip();
+                (tip, select_transactions(&blockchain, &mempool, &tip))
+            };
+            let key = (tip, block_transactions.iter().map(|st| st.hash()).collect::<Vec<H256>>());
+            if template_key.as_ref() == Some(&key) {
+                continue;
             }
-            
-            if !(temp_parent == parent){
-                // Clear the mempool of expired transactions:
-
-                {
-                    let mut memorypool = self.mempool.lock().unwrap();
-                    for (key, value) in memorypool.map.clone().into_iter(){
-                        let transaction = value.get_transaction();
-                        let sendery = transaction.get_sender();
-                        let acny = transaction.get_account_nonce();
-
-                        let mut new_nonce: u32;
-                        {
-                            (new_nonce, _) = *self.blockchain.lock().unwrap().state_map.get(&temp_parent.clone()).unwrap().get(&sendery).unwrap();
-                        }
-
-                        if acny <= new_nonce{
-                            memorypool.map.remove(&key);
-                        }
-
+            // honest mining only seals blocks with transactions; a withholding strategy also mines empty
+            // blocks, since the transactions its branch could take are usually already in the public chain.
+            // The consensus engine either seals the block right away or leaves a nonce search to the hashing threads
+            let template = if block_transactions.is_empty() && private.is_none() {
+                None
+            } else {
+                let (dify, consensus, height) = {
+                    let blockchain = self.blockchain.lock().unwrap();
+                    (blockchain.map[&tip].get_difficulty(), Arc::clone(&blockchain.consensus), blockchain.level_map[&tip] + 1)
+                };
+                let mut block = generate_block(&tip, &dify, &block_transactions);
+                match consensus.seal(&mut block.header, height) {
+                    Seal::Search => Some(block),
+                    Seal::Sealed => {
+                        self.publish(&block, &mut private);
+                        None
                     }
+                    Seal::NotOurTurn => None,
                 }
+            };
+            let template_info = template.as_ref().map(|block| (tip, block.content.0.len()));
+            self.update_status(|status| status.template = template_info);
+            engine.set_template(template);
+            template_key = Some(key);
+        }
+    }
+
+    /// Insert a block sealed by this node and hand it over for broadcast, unless a withholding strategy keeps it
+    fn publish(&self, block: &Block, private: &mut Option<PrivateChain>) {
+        let mut blockchain = self.blockchain.lock().unwrap();
+        let stale = private.is_none() && blockchain.tip() != block.get_parent();
+        match private {
+            Some(_) => blockchain.insert_withheld(block),
+            None => blockchain.insert(block),
+        }
+        println!("mined block");
+        self.count_mined(stale);
+        match private.as_mut() {
+            Some(private) => {
+                let level = blockchain.level_map[&block.hash()];
+                let released = private.on_mined(block.clone(), level);
+                self.release(released, &mut blockchain);
+                let withheld = private.withheld();
+                self.update_status(|status| status.withheld_blocks = withheld);
+            }
+            None => self.release(vec![block.clone()], &mut blockchain),
+        }
+    }
 
-                
+    /// Let blocks take part in the fork choice and hand them over to the miner worker, which broadcasts them
+    fn release(&self, blocks: Vec<Block>, blockchain: &mut Blockchain) {
+        for block in blocks {
+            blockchain.reveal(&block.hash());
+            self.finished_block_chan.send(block).expect("Send finished block error");
+        }
+    }
 
-                parent = temp_parent.clone();
+    fn count_mined(&self, stale: bool) {
+        self.update_status(|status| {
+            status.blocks_mined += 1;
+            if stale {
+                status.stale_blocks += 1;
             }
-            
-            // Get new transactions to put in the block:
-            block_transactions.clear();
-            {
-                cont =0;
-                for (key, value) in self.mempool.lock().unwrap().map.clone().into_iter(){
-
-                    let mut block_state: HashMap<Address, (u32, u32)>;
-                    {
-                        block_state = self.blockchain.lock().unwrap().state_map.get(&temp_parent.clone()).unwrap().clone();
-                    }
+        });
+    }
+
+    fn update_status<F: FnOnce(&mut Status)>(&self, f: F) {
+        f(&mut self.status.lock().unwrap());
+    }
+}
 
-                    let transaction = value.get_transaction();
-                    let sendery = transaction.get_sender();
-                    let valuey = transaction.get_value();
-                    let acny = transaction.get_account_nonce();
-
-                    if block_state.contains_key(&sendery){
-                        let (send_an, send_bal) = *block_state.get(&sendery).unwrap();
-                        if acny==(send_an+1) {
-                            if valuey <= send_bal{
-                                if !used_addresses.contains(&sendery){
-                                    keys_to_remove.push(key.clone());
-                                    used_addresses.push(sendery);
-                                    cont = cont+1;
-                                    if cont >= max_transaction_count{
-                                        break;
-                                    }
-                                }
-                                
-                            } 
+/// Most blocks `generate_blocks` mines in one call
+pub const MAX_GENERATED_BLOCKS: u64 = 100;
+
+/// Mine `count` blocks on the tip right away, each with the transactions the miner would pick, and insert
+/// them. Meant for regtest, where any nonce meets the difficulty. Blocks may be empty. Under proof of
+/// authority, stops at the first block another signer has to seal.
+pub fn generate_blocks(blockchain: &Arc<Mutex<Blockchain>>, mempool: &Arc<Mutex<Mempool>>, count: u64) -> Result<Vec<Block>, String> {
+    if count > MAX_GENERATED_BLOCKS {
+        return Err(format!("at most {} blocks can be generated at once", MAX_GENERATED_BLOCKS));
+    }
+    let mut blocks: Vec<Block> = Vec::new();
+    for _ in 0..count {
+        // lock order: mempool before blockchain, as in the network worker
+        let mut mempool = mempool.lock().unwrap();
+        let mut blockchain = blockchain.lock().unwrap();
+        let tip = blockchain.tip();
+        prune_mempool(&blockchain, &mut mempool, &tip);
+        let block_transactions = select_transactions(&blockchain, &mempool, &tip);
+        let mut block = generate_block(&tip, &blockchain.map[&tip].get_difficulty(), &block_transactions);
+        match blockchain.consensus.seal(&mut block.header, blockchain.tip_level + 1) {
+            Seal::Sealed => {}
+            Seal::Search => {
+                block.header.nonce = 0;
+                while !meets_target(&block.header) {
+                    match block.header.nonce.checked_add(1) {
+                        Some(nonce) => block.header.nonce = nonce,
+                        None => {
+                            block.header.nonce = 0;
+                            block.header.extra_nonce += 1;
                         }
                     }
-                    
                 }
             }
-            
-            for i in 0..keys_to_remove.len(){
-                block_transactions.push(self.mempool.lock().unwrap().map.get(&keys_to_remove[i]).unwrap().clone());
-            }  
-            keys_to_remove.clear();
-
-            if let OperatingState::Run(i) = self.operating_state {
-                if i != 0 {
-                    let interval = time::Duration::from_micros(i as u64);
-                    thread::sleep(interval);
-                }
+            Seal::NotOurTurn if blocks.is_empty() => {
+                return Err(format!("this node may not seal the block at height {}", blockchain.tip_level + 1));
+            }
+            Seal::NotOurTurn => break,
+        }
+        blockchain.insert(&block);
+        blocks.push(block);
+    }
+    Ok(blocks)
+}
+
+/// Remove the mempool transactions whose account nonce is already used in the state after `tip`
+pub fn prune_mempool(blockchain: &Blockchain, mempool: &mut Mempool, tip: &H256) {
+    let tip_state = blockchain.state_map.get(tip).unwrap();
+    for (key, value) in mempool.map.clone().into_iter(){
+        let transaction = value.get_transaction();
+        if let Some((new_nonce, _)) = tip_state.get(&transaction.get_sender()) {
+            if transaction.get_account_nonce() <= *new_nonce{
+                mempool.remove(&key);
             }
         }
     }
@@ -341,7 +483,16 @@ impl Context {
 #[cfg(test)]
 mod test {
     use ntest::timeout;
-    use crate::types::hash::Hashable;
+    use crate::blockchain::{Blockchain, Mempool};
+    use crate::consensus::Pow;
+    use crate::miner::strategy::Strategy;
+    use crate::types::address::Address;
+    use crate::types::block::generate_block;
+    use crate::types::hash::{H256, Hashable};
+    use crate::types::transaction::{sign, sig_to_vec, SignedTransaction, Transaction, DEFAULT_CHAIN_ID};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::sync::{Arc, Mutex};
+    use std::{thread, time};
 
     #[test]
     #[timeout(60000)]
@@ -356,6 +507,115 @@ mod test {
             block_prev = block_next;
         }
     }
+
+    #[test]
+    fn generate_regtest_blocks() {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new_regtest(0, Arc::new(Pow))));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let blocks = super::generate_blocks(&blockchain, &mempool, 3).unwrap();
+        assert_eq!(blocks.len(), 3);
+        let blockchain = blockchain.lock().unwrap();
+        assert_eq!(blockchain.tip(), blocks[2].hash());
+        assert_eq!(blockchain.tip_level, 3);
+        assert_eq!(blocks[1].get_parent(), blocks[0].hash());
+    }
+
+    #[test]
+    fn generate_regtest_blocks_with_transactions() {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new_regtest(DEFAULT_CHAIN_ID, Arc::new(Pow))));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let payment = genesis_payment(1);
+        let sender = payment.get_transaction().get_sender();
+        mempool.lock().unwrap().insert(&payment);
+        let genesis = blockchain.lock().unwrap().tip();
+        let (_, funds) = blockchain.lock().unwrap().state_map[&genesis][&sender];
+
+        let blocks = super::generate_blocks(&blockchain, &mempool, 2).unwrap();
+        assert_eq!(blocks[0].get_transactions(), vec![payment.hash()]);
+        assert!(blocks[1].get_transactions().is_empty());
+        assert!(mempool.lock().unwrap().map.is_empty());
+        assert_eq!(blockchain.lock().unwrap().state_map[&blocks[0].hash()][&sender], (1, funds - 1));
+        assert!(super::generate_blocks(&blockchain, &mempool, super::MAX_GENERATED_BLOCKS + 1).is_err());
+        assert_eq!(blockchain.lock().unwrap().tip(), blocks[1].hash());
+    }
+
+    /// Payment from the account funded in the genesis state
+    fn genesis_payment(account_nonce: u32) -> SignedTransaction {
+        let key = Ed25519KeyPair::from_seed_unchecked(&[0; 32]).unwrap();
+        let sender = Address::from_public_key_bytes(key.public_key().as_ref());
+        let t = Transaction::new(sender, Address::default(), 1, account_nonce, DEFAULT_CHAIN_ID);
+        SignedTransaction::new(t.clone(), sig_to_vec(sign(&t, &key)), key.public_key().as_ref().to_vec())
+    }
+
+    fn wait_for_status<F: Fn(&super::Status) -> bool>(handle: &super::Handle, done: F) -> super::Status {
+        loop {
+            let status = handle.status();
+            if done(&status) {
+                return status;
+            }
+            thread::sleep(time::Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    #[timeout(60000)]
+    fn start_pause_stop() {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new_regtest(DEFAULT_CHAIN_ID, Arc::new(Pow))));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        mempool.lock().unwrap().insert(&genesis_payment(1));
+        let (miner_ctx, miner_handle, finished_block_chan) = super::new(&blockchain, &mempool, 1);
+        miner_ctx.start();
+        let status = miner_handle.status();
+        assert_eq!((status.state, status.lambda, status.template), ("paused", None, None));
+
+        // any hash meets the regtest difficulty, so the payment is mined at once
+        assert!(miner_handle.start(0));
+        let block = finished_block_chan.recv().unwrap();
+        assert_eq!(block.content.0.len(), 1);
+        let status = miner_handle.status();
+        assert_eq!((status.blocks_mined, status.stale_blocks), (1, 0));
+
+        // a tip with a zero target can not be mined on, which keeps the next template in place
+        let unminable = generate_block(&block.hash(), &H256::default(), &Vec::new());
+        blockchain.lock().unwrap().insert(&unminable);
+        mempool.lock().unwrap().insert(&genesis_payment(2));
+        miner_handle.update();
+        let status = wait_for_status(&miner_handle, |status| status.template.is_some());
+        assert_eq!((status.state, status.lambda, status.template), ("running", Some(0), Some((unminable.hash(), 1))));
+
+        assert!(miner_handle.pause());
+        let status = wait_for_status(&miner_handle, |status| status.state == "paused");
+        assert_eq!((status.lambda, status.template, status.hashrate), (None, None, 0.0));
+
+        miner_handle.exit();
+        let status = wait_for_status(&miner_handle, |status| status.state == "shut down");
+        assert_eq!((status.lambda, status.blocks_mined, status.stale_blocks), (None, 1, 0));
+        // a stopped miner ignores signals
+        wait_for_status(&miner_handle, |_| !miner_handle.pause());
+        assert!(!miner_handle.start(0));
+        miner_handle.update();
+    }
+
+    #[test]
+    #[timeout(60000)]
+    fn fork_from_height_mines_empty_blocks() {
+        let blockchain = Arc::new(Mutex::new(Blockchain::new_regtest(0, Arc::new(Pow))));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let public = super::generate_blocks(&blockchain, &mempool, 3).unwrap();
+        let (miner_ctx, miner_handle, finished_block_chan) = super::new(&blockchain, &mempool, 1);
+        miner_ctx.start();
+        // nothing to mine on the fork from level 1, the public chain is 2 blocks ahead of it
+        assert!(miner_handle.start_with_strategy(0, Strategy::ForkFromHeight(1)));
+        // the fork is published at once when it gets longer than the public chain
+        let fork: Vec<_> = (0..3).map(|_| finished_block_chan.recv().unwrap()).collect();
+        miner_handle.exit();
+        assert_eq!(fork[0].get_parent(), public[0].hash());
+        assert_eq!(fork[1].get_parent(), fork[0].hash());
+        assert_eq!(fork[2].get_parent(), fork[1].hash());
+        let blockchain = blockchain.lock().unwrap();
+        assert!(blockchain.tip_level >= 4);
+        assert!(blockchain.all_blocks_in_longest_chain().contains(&fork[2].hash()));
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
@@ -0,0 +1,243 @@
+//! Block withholding strategies, for reproducing attacks on the longest chain rule.
+//!
+//! A withholding miner inserts the blocks it mines into its own blockchain, so it can build on them and
+//! serve them to peers, but only hands them to the miner worker for broadcast once the strategy
+//! releases them. Until then they are kept out of the fork choice (see `Blockchain::insert_withheld`),
+//! so the tip, and with it the API and transaction validation, follows the published chain.
+
+use std::collections::VecDeque;
+
+use crate::blockchain::Blockchain;
+use crate::types::block::Block;
+use crate::types::hash::{H256, Hashable};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    /// publish every block as soon as it is mined
+    Honest,
+    /// selfish mining (Eyal and Sirer): keep a private chain, revealing just enough of it to
+    /// override the public chain whenever that one catches up
+    Selfish,
+    /// mine a private fork from the block at this level of the longest chain, publishing it as soon
+    /// as it is longer than the public chain; the fork is never abandoned
+    ForkFromHeight(u64),
+}
+
+impl Strategy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Strategy::Honest => "honest",
+            Strategy::Selfish => "selfish",
+            Strategy::ForkFromHeight(_) => "fork",
+        }
+    }
+}
+
+/// Branch mined by a withholding strategy
+pub struct PrivateChain {
+    strategy: Strategy,
+    // last block of the branch being mined, and its level
+    tip: H256,
+    level: u64,
+    // mined blocks not published yet with their levels, oldest first
+    withheld: VecDeque<(u64, Block)>,
+    // height of the public chain at the last check
+    public_level: u64,
+    // the published private branch is as long as the public chain, the next block settles the race
+    race: bool,
+}
+
+impl PrivateChain {
+    /// Start a private branch for `strategy` on the current blockchain; `None` for honest mining
+    pub fn new(strategy: Strategy, blockchain: &Blockchain) -> Option<Self> {
+        let (tip, level) = match strategy {
+            Strategy::Honest => return None,
+            Strategy::Selfish => (blockchain.tip(), blockchain.tip_level),
+            Strategy::ForkFromHeight(height) => {
+                let level = height.min(blockchain.tip_level);
+                (blockchain.all_blocks_in_longest_chain()[level as usize], level)
+            }
+        };
+        Some(PrivateChain {
+            strategy,
+            tip,
+            level,
+            withheld: VecDeque::new(),
+            public_level: blockchain.tip_level,
+            race: false,
+        })
+    }
+
+    /// Block to mine on
+    pub fn tip(&self) -> H256 {
+        self.tip
+    }
+
+    pub fn withheld(&self) -> usize {
+        self.withheld.len()
+    }
+
+    /// Add a block mined on `tip()`, already inserted at `level`. Returns the blocks to publish.
+    pub fn on_mined(&mut self, block: Block, level: u64) -> Vec<Block> {
+        self.tip = block.hash();
+        self.level = level;
+        self.withheld.push_back((level, block));
+        match self.strategy {
+            Strategy::Selfish if self.race => {
+                // our published branch was tied with the public one, this block breaks the tie
+                self.race = false;
+                self.release(u64::MAX)
+            }
+            Strategy::ForkFromHeight(_) if self.level > self.public_level => self.release(u64::MAX),
+            _ => Vec::new(),
+        }
+    }
+
+    /// React to the public chain growing. Returns the blocks to publish, and the number of withheld
+    /// blocks given up because the public chain got longer than the private one.
+    pub fn on_public(&mut self, blockchain: &Blockchain) -> (Vec<Block>, usize) {
+        // withheld blocks never become the tip, so the tip is the head of the published chain
+        let (public_tip, public_level) = (blockchain.tip(), blockchain.tip_level);
+        if public_level <= self.public_level {
+            return (Vec::new(), 0);
+        }
+        self.public_level = public_level;
+        if self.strategy != Strategy::Selfish {
+            return (Vec::new(), 0);
+        }
+        if public_level > self.level {
+            // the public chain won: mine on it
+            let abandoned = self.withheld.len();
+            self.withheld.clear();
+            self.tip = public_tip;
+            self.level = public_level;
+            self.race = false;
+            (Vec::new(), abandoned)
+        } else if self.level - public_level <= 1 {
+            // a lead of one block is a tie to race, a lead of two is a win: publish everything
+            self.race = self.level == public_level;
+            (self.release(u64::MAX), 0)
+        } else {
+            // still well ahead: publish as much as the public chain has, to make it lose
+            (self.release(public_level), 0)
+        }
+    }
+
+    /// Remove the withheld blocks up to `level`
+    fn release(&mut self, level: u64) -> Vec<Block> {
+        let mut released: Vec<Block> = Vec::new();
+        while let Some((block_level, _)) = self.withheld.front() {
+            if *block_level > level {
+                break;
+            }
+            released.push(self.withheld.pop_front().unwrap().1);
+        }
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::block::generate_random_block;
+
+    /// Mine a public block on `parent` into `blockchain`, returning it with its level
+    fn mine_on(blockchain: &mut Blockchain, parent: &H256) -> (Block, u64) {
+        let block = generate_random_block(parent);
+        blockchain.insert(&block);
+        let level = blockchain.level_map[&block.hash()];
+        (block, level)
+    }
+
+    /// Mine a block on the private branch, as the miner does: inserted as withheld, revealed once released
+    fn mine_private(blockchain: &mut Blockchain, private: &mut PrivateChain) -> Vec<Block> {
+        let block = generate_random_block(&private.tip());
+        blockchain.insert_withheld(&block);
+        let level = blockchain.level_map[&block.hash()];
+        let released = private.on_mined(block, level);
+        reveal(blockchain, &released);
+        released
+    }
+
+    fn on_public(blockchain: &mut Blockchain, private: &mut PrivateChain) -> (Vec<Block>, usize) {
+        let (released, abandoned) = private.on_public(blockchain);
+        reveal(blockchain, &released);
+        (released, abandoned)
+    }
+
+    fn reveal(blockchain: &mut Blockchain, released: &[Block]) {
+        for block in released {
+            blockchain.reveal(&block.hash());
+        }
+    }
+
+    #[test]
+    fn selfish_mining() {
+        let mut blockchain = Blockchain::new();
+        let genesis = blockchain.tip();
+        let mut private = PrivateChain::new(Strategy::Selfish, &blockchain).unwrap();
+        assert!(PrivateChain::new(Strategy::Honest, &blockchain).is_none());
+
+        // three private blocks are withheld, and the tip stays on the published chain
+        let mut mined: Vec<H256> = Vec::new();
+        for _ in 0..3 {
+            assert!(mine_private(&mut blockchain, &mut private).is_empty());
+            mined.push(private.tip());
+        }
+        assert_eq!(private.withheld(), 3);
+        assert_eq!(blockchain.tip(), genesis);
+
+        // the public chain catching up releases as many blocks as it has, then everything at a lead of one
+        let (public_1, _) = mine_on(&mut blockchain, &genesis);
+        let (released, abandoned) = on_public(&mut blockchain, &mut private);
+        assert_eq!((released.len(), abandoned), (1, 0));
+        assert_eq!(released[0].hash(), mined[0]);
+        // the released block only ties with the public one, which was seen first
+        assert_eq!(blockchain.tip(), public_1.hash());
+        let (public_2, _) = mine_on(&mut blockchain, &public_1.hash());
+        let (released, _) = on_public(&mut blockchain, &mut private);
+        assert_eq!(released.iter().map(|b| b.hash()).collect::<Vec<H256>>(), vec![mined[1], mined[2]]);
+        assert_eq!(private.withheld(), 0);
+        assert_eq!(blockchain.tip(), mined[2]);
+
+        // a tie is raced: the next private block is published at once
+        assert!(mine_private(&mut blockchain, &mut private).is_empty());
+        let (public_3, _) = mine_on(&mut blockchain, &public_2.hash());
+        mine_on(&mut blockchain, &public_3.hash());
+        let (released, _) = on_public(&mut blockchain, &mut private);
+        assert_eq!(released.len(), 1);
+        assert_eq!(mine_private(&mut blockchain, &mut private).len(), 1);
+        assert_eq!(blockchain.tip(), private.tip());
+
+        // a longer public chain is adopted, giving up the withheld blocks
+        let published = private.tip();
+        assert!(mine_private(&mut blockchain, &mut private).is_empty());
+        let (honest_1, _) = mine_on(&mut blockchain, &published);
+        let (honest_2, _) = mine_on(&mut blockchain, &honest_1.hash());
+        let (released, abandoned) = on_public(&mut blockchain, &mut private);
+        assert!(released.is_empty());
+        assert_eq!(abandoned, 1);
+        assert_eq!(private.tip(), honest_2.hash());
+        assert_eq!(private.withheld(), 0);
+    }
+
+    #[test]
+    fn fork_from_height() {
+        let mut blockchain = Blockchain::new();
+        let mut public_tip = blockchain.tip();
+        for _ in 0..3 {
+            public_tip = mine_on(&mut blockchain, &public_tip).0.hash();
+        }
+        let fork_base = blockchain.all_blocks_in_longest_chain()[1];
+        let mut private = PrivateChain::new(Strategy::ForkFromHeight(1), &blockchain).unwrap();
+        assert_eq!(private.tip(), fork_base);
+
+        // the fork is withheld until it is longer than the public chain
+        for _ in 0..2 {
+            assert!(mine_private(&mut blockchain, &mut private).is_empty());
+        }
+        assert_eq!(blockchain.tip(), public_tip);
+        assert_eq!(mine_private(&mut blockchain, &mut private).len(), 3);
+        assert_eq!(blockchain.tip(), private.tip());
+    }
+}
@@ -8,8 +8,8 @@ use crate::types::address::Address;
 
 use std::ops::Add;
 use std::sync::{Arc, Mutex};
-use crate::blockchain::{Blockchain, Mempool};
-use crate::types::block::Block;
+use crate::blockchain::{Blockchain, Mempool, SignatureCache};
+use crate::types::block::{Block, HEADER_VERSION, MAX_BLOCK_SIZE};
 use std::collections::HashMap;
 
 use log::{debug, warn, error};
@@ -27,6 +27,9 @@ pub struct Worker {
     server: ServerHandle,
     blockchain: Arc<Mutex<Blockchain>>,
     mempool: Arc<Mutex<Mempool>>,
+    sig_cache: Arc<Mutex<SignatureCache>>,
+    // threads used to verify the signatures of one block
+    verify_threads: usize,
 }
 
 
@@ -37,6 +40,7 @@ impl Worker {
         server: &ServerHandle,
         blockchain: &Arc<Mutex<Blockchain>>, 
         mempool: &Arc<Mutex<Mempool>>,
+        sig_cache: &Arc<Mutex<SignatureCache>>,
     ) -> Self {
         Self {
             msg_chan: msg_src,
@@ -44,6 +48,8 @@ impl Worker {
             server: server.clone(),
             blockchain: Arc::clone(blockchain),
             mempool: Arc::clone(mempool),
+            sig_cache: Arc::clone(sig_cache),
+            verify_threads: thread::available_parallelism().map_or(1, |n| n.get()),
         }
     }
 
@@ -58,6 +64,50 @@ impl Worker {
         }
     }
 
+    /// Check the signatures of every transaction in `block` at `height` in parallel, skipping transactions
+    /// already in the signature cache. Runs without holding the blockchain lock.
+    fn verify_block_signatures(&self, block: &Block, chain_id: u32, height: u64) -> bool {
+        let unverified: Vec<SignedTransaction> = {
+            let cache = self.sig_cache.lock().unwrap();
+            block.get_transaction_details().into_iter().filter(|st| !cache.contains(&st.hash())).collect()
+        };
+        let results = transaction::verify_signed_batch(&unverified, chain_id, height, self.verify_threads);
+        let mut cache = self.sig_cache.lock().unwrap();
+        for (st, verified) in unverified.iter().zip(results.iter()) {
+            // script results depend on the height, so they are never cached
+            if *verified && st.get_script().is_none() {
+                cache.insert(st.hash());
+            }
+        }
+        results.iter().all(|verified| *verified)
+    }
+
+    /// Check the signatures of `block` before taking the chain lock to insert it. Returns the height they
+    /// were checked at with the result, or `None` if there is nothing to check yet: the block is known,
+    /// or its parent is not.
+    fn precheck_block_signatures(&self, block: &Block) -> Option<(u64, bool)> {
+        let (parent_known, chain_id, height) = {
+            let b_chain = self.blockchain.lock().unwrap();
+            let parent_level = b_chain.level_map.get(&block.get_parent()).copied();
+            (!b_chain.map.contains_key(&block.hash()) && parent_level.is_some(), b_chain.chain_id, parent_level.unwrap_or(0) + 1)
+        };
+        if !parent_known {
+            return None;
+        }
+        Some((height, self.verify_block_signatures(block, chain_id, height)))
+    }
+
+    /// Whether the signatures of `block`, whose parent is in `b_chain`, are valid. Under the chain lock:
+    /// when the pre-check did not cover the block's height, because another thread inserted the parent
+    /// in the meantime, the signatures are checked now.
+    fn block_signatures_valid(&self, block: &Block, precheck: Option<(u64, bool)>, b_chain: &Blockchain) -> bool {
+        let height = b_chain.level_map[&block.get_parent()] + 1;
+        match precheck {
+            Some((checked_height, valid)) if checked_height == height => valid,
+            _ => self.verify_block_signatures(block, b_chain.chain_id, height),
+        }
+    }
+
     fn worker_loop(&self) {
         let mut orphan_buffer: HashMap<H256, Block> = HashMap::new();
         let mut orphan_parents: Vec<H256> = Vec::new();
@@ -140,28 +190,52 @@ impl Worker {
                         for i in 0..nonce.len(){
                             let hash = nonce[i].clone().hash();
                             let parenty = nonce[i].clone().header.parent;
-                            let difficy = nonce[i].clone().header.difficulty;
                             let this_block = nonce[i].clone();
+                            // verify signatures before taking the chain lock, only for blocks that could be inserted
+                            let precheck = self.precheck_block_signatures(&this_block);
                             {
                                 // might want to make it so it just get what it needs quickly from the bchain (don't copy the whole thing)
 
                                 let mut b_chain = self.blockchain.lock().unwrap();
                                 if !b_chain.map.contains_key(&hash){
                                     if b_chain.map.contains_key(&parenty){
-                                        if (difficy == b_chain.map[&parenty].header.difficulty) & (hash <= difficy){
+                                        let height = b_chain.level_map[&parenty] + 1;
+                                        let signatures_valid = self.block_signatures_valid(&this_block, precheck, &b_chain);
+                                        if b_chain.consensus.verify_seal(&this_block.header, &b_chain.map[&parenty].header, height).is_ok(){
                                             // check if all transactions in the block are valid
                                             let mut block_state: HashMap<Address, (u32, u32)> = b_chain.state_map.get(&parenty.clone()).unwrap().clone();
                                             // go through previous state and change the values in any account in which there was a transaction:
                                             let block_details = this_block.get_transaction_details();
 
                                             let mut validity_check = 0;
+                                            if !signatures_valid{
+                                                validity_check += 1;
+                                            }
+                                            if this_block.content_size() > MAX_BLOCK_SIZE{
+                                                validity_check += 1;
+                                            }
+                                            if this_block.header.version != HEADER_VERSION{
+                                                validity_check += 1;
+                                            }
 
                                             let mut used_senders: Vec<Address> = Vec::new();
                                             for signed_t in block_details.into_iter(){
                                                 let transaction = signed_t.get_transaction();
                                                 let sendery = transaction.get_sender();
-                                                let valuey = transaction.get_value();
+                                                let valuey = match transaction.get_total_value() {
+                                                    Some(v) => v,
+                                                    None => {
+                                                        validity_check += 1; // outputs overflow a u32
+                                                        0
+                                                    }
+                                                };
                                                 let acny = transaction.get_account_nonce();
+                                                if !b_chain.is_unlocked(&transaction, &parenty){
+                                                    validity_check += 1;
+                                                }
+                                                if !b_chain.htlc_allowed(&transaction, &parenty){
+                                                    validity_check += 1;
+                                                }
                                                 if block_state.contains_key(&sendery){
                                                     let (send_an, send_bal) = *block_state.get(&sendery).unwrap();
                                                     if !acny==(send_an+1) {
@@ -201,14 +275,15 @@ impl Worker {
                                     }
                                     
                                     else{
-                                        if (!orphan_buffer.contains_key(&hash)) & (hash <= difficy){
+                                        let plausible = b_chain.consensus.plausible_seal(&this_block.header);
+                                        if (!orphan_buffer.contains_key(&hash)) & plausible{
                                             orphan_buffer.insert(hash.clone(), nonce[i].clone());
                                             orphan_parents.push(parenty.clone());
                                             orphan_children.push(hash);
                                             // ask for parent whenever you insert a orphan into the buffer
                                             // shouldn't you ask for the parents whenever you get a new block? 
                                         }
-                                        if hash <= difficy { 
+                                        if plausible { 
                                             let mut missing_parent: Vec<H256> = Vec::new();
                                             missing_parent.push(parenty.clone());
                                             peer.write(Message::GetBlocks(missing_parent))
@@ -270,44 +345,43 @@ impl Worker {
                     // if transaction is verified, and it is not in the mempool already add it to the mempool:
                     for i in 0..nonce.len(){
                         let transaction = nonce[i].get_transaction().clone();
-                        let signature = nonce[i].get_signature().clone();
-                        let public_key = nonce[i].get_public_key().clone();
                         let sender = transaction.get_sender().clone();
 
                         let sender_clone = sender.clone();
                         let proposed_an = transaction.get_account_nonce();
 
-                        let public_address = Address::from_public_key_bytes(public_key.as_ref()); // note this hashes the public key!
-
                         let nonce_hash = nonce[i].clone().hash();
+                        let (chain_id, next_height) = {
+                            let b_chain = self.blockchain.lock().unwrap();
+                            (b_chain.chain_id, b_chain.tip_level + 1)
+                        };
+                        // checks the signature(s) and that they authorize spending from the sender address
+                        let verified = self.sig_cache.lock().unwrap().contains(&nonce_hash) || transaction::verify_signed(&nonce[i], chain_id, next_height);
+                        if verified && nonce[i].get_script().is_none() {
+                            self.sig_cache.lock().unwrap().insert(nonce_hash);
+                        }
 
-                        let Address(compare_one) = sender;
-                        let Address(compare_two) = public_address;
-
-                        
                         // NOTE: May need to mess around with the scope if you run into bugs !!!!
                         {
                             let mut mpool = self.mempool.lock().unwrap();
-                            if transaction::verify(&transaction, &public_key, &signature) & !mpool.map.contains_key(&nonce_hash) { // Is this & symbol working as expected?
+                            if verified & !mpool.map.contains_key(&nonce_hash) { // Is this & symbol working as expected?
                                 // println!("verified");
-                                if compare_one == compare_two{
-                                    // println!("compared");
-                                    let mut tip_state: HashMap<Address, (u32, u32)>;
-                                    {
-                                        let tip_hash = self.blockchain.lock().unwrap().tip();
-                                        tip_state = self.blockchain.lock().unwrap().state_map.get(&tip_hash).unwrap().clone();
-                                    }
-                                    
-                                    if tip_state.contains_key(&sender_clone.clone()){
-                                        let (tip_account_nonce, send_balance) = *tip_state.get(&sender_clone).unwrap();
-                                        if proposed_an > tip_account_nonce{
+                                let tip_state: HashMap<Address, (u32, u32)>;
+                                let htlc_allowed: bool;
+                                {
+                                    let b_chain = self.blockchain.lock().unwrap();
+                                    let tip_hash = b_chain.tip();
+                                    tip_state = b_chain.state_map.get(&tip_hash).unwrap().clone();
+                                    // HTLC steps must fit the locks open at the tip
+                                    htlc_allowed = b_chain.htlc_allowed(&transaction, &tip_hash);
+                                }
+                                
+                                if htlc_allowed && tip_state.contains_key(&sender_clone.clone()){
+                                    let (tip_account_nonce, _) = *tip_state.get(&sender_clone).unwrap();
+                                    if proposed_an > tip_account_nonce{
 
-                                            mpool.insert(&nonce[i]);
-                                        }
+                                        mpool.insert(&nonce[i]);
                                     }
-                                    
-                                    
-                                    
                                 }
                                 
                             }
@@ -352,7 +426,8 @@ fn generate_test_worker_and_start() -> (TestMsgSender, ServerTestReceiver, Vec<H
     let wrapped_bc = Arc::new(Mutex::new(new_bc));
     let testpool = Mempool::new();
     let wrapped_tp = Arc::new(Mutex::new(testpool));
-    let worker = Worker::new(1, msg_chan, &server, &wrapped_bc, &wrapped_tp);
+    let wrapped_cache = Arc::new(Mutex::new(SignatureCache::new(1000)));
+    let worker = Worker::new(1, msg_chan, &server, &wrapped_bc, &wrapped_tp, &wrapped_cache);
     let mut longest_chain_hashes: Vec<H256> = Vec::new();
     {
         longest_chain_hashes = wrapped_bc.lock().unwrap().all_blocks_in_longest_chain(); // probably could subsitute this with the genesis block hash directly
@@ -372,6 +447,12 @@ mod test {
 
     use super::super::message::Message;
     use super::generate_test_worker_and_start;
+    use super::{Blockchain, Mempool, ServerHandle, SignatureCache, SignedTransaction, TestMsgSender, Worker};
+    use crate::types::block::generate_block;
+    use crate::consensus::{Consensus, Poa, Seal};
+    use crate::types::transaction::{generate_random_transaction_1, DEFAULT_CHAIN_ID};
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::sync::{Arc, Mutex};
 
     #[test]
     #[timeout(60000)]
@@ -413,6 +494,68 @@ mod test {
             panic!();
         }
     }
+    #[test]
+    #[timeout(60000)]
+    fn signatures_checked_when_parent_arrives_late() {
+        let (server, _server_receiver) = ServerHandle::new_for_test();
+        let (_test_msg_sender, msg_chan) = TestMsgSender::new();
+        let blockchain = Arc::new(Mutex::new(Blockchain::new()));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let sig_cache = Arc::new(Mutex::new(SignatureCache::new(1000)));
+        let worker = Worker::new(1, msg_chan, &server, &blockchain, &mempool, &sig_cache);
+
+        let genesis = blockchain.lock().unwrap().tip();
+        let parent = generate_random_block(&genesis);
+        let good = generate_random_transaction_1();
+        let bad = SignedTransaction::new(good.get_transaction(), vec![0; 64], good.get_public_key());
+        let bad_block = generate_block(&parent.hash(), &parent.get_difficulty(), &vec![bad]);
+        let good_block = generate_block(&parent.hash(), &parent.get_difficulty(), &vec![good]);
+
+        // the parent is unknown when the signatures are pre-checked, so nothing is checked
+        assert_eq!(worker.precheck_block_signatures(&bad_block), None);
+        // another thread inserts the parent before the block is inserted
+        blockchain.lock().unwrap().insert(&parent);
+        let b_chain = blockchain.lock().unwrap();
+        assert!(!worker.block_signatures_valid(&bad_block, None, &b_chain));
+        assert!(worker.block_signatures_valid(&good_block, None, &b_chain));
+        // a pre-check at another height is not trusted either
+        assert!(!worker.block_signatures_valid(&bad_block, Some((7, true)), &b_chain));
+    }
+    #[test]
+    #[timeout(60000)]
+    fn poa_block_sealed_out_of_turn() {
+        let (server, server_receiver) = ServerHandle::new_for_test();
+        let (test_msg_sender, msg_chan) = TestMsgSender::new();
+        let key = |seed: u8| Ed25519KeyPair::from_seed_unchecked(&[seed; 32]).unwrap();
+        let signers: Vec<Vec<u8>> = (1..=2).map(|seed| key(seed).public_key().as_ref().to_vec()).collect();
+        let consensus = Arc::new(Poa::new(signers.clone(), None));
+        let blockchain = Arc::new(Mutex::new(Blockchain::new_with_consensus(DEFAULT_CHAIN_ID, consensus)));
+        let mempool = Arc::new(Mutex::new(Mempool::new()));
+        let sig_cache = Arc::new(Mutex::new(SignatureCache::new(1000)));
+        Worker::new(1, msg_chan, &server, &blockchain, &mempool, &sig_cache).start();
+
+        // the block at height 1 is the second signer's turn
+        let genesis = blockchain.lock().unwrap().tip();
+        let difficulty = blockchain.lock().unwrap().map[&genesis].get_difficulty();
+        // sealed by `seed` as if the block were at `height`
+        let seal = |seed: u8, height: u64| {
+            let mut block = generate_block(&genesis, &difficulty, &Vec::new());
+            assert!(matches!(Poa::new(signers.clone(), Some(key(seed))).seal(&mut block.header, height), Seal::Sealed));
+            block
+        };
+        let out_of_turn = seal(1, 2);
+        let in_turn = seal(2, 1);
+        let _peer_receiver = test_msg_sender.send(Message::Blocks(vec![out_of_turn.clone()]));
+        let _peer_receiver = test_msg_sender.send(Message::Blocks(vec![in_turn.clone()]));
+        // blocks are handled in order, so the first one was rejected once the second one is announced
+        match server_receiver.recv() {
+            Some(Message::NewBlockHashes(hashes)) => assert_eq!(hashes, vec![in_turn.hash()]),
+            _ => panic!(),
+        }
+        let blockchain = blockchain.lock().unwrap();
+        assert!(!blockchain.map.contains_key(&out_of_turn.hash()));
+        assert_eq!(blockchain.tip(), in_turn.hash());
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
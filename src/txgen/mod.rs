@@ -58,7 +58,7 @@ pub struct Handle {
     control_chan: Sender<ControlSignal>,
 }
 
-pub fn new(mempool: &Arc<Mutex<Mempool>>, blockchain: &Arc<Mutex<Blockchain>>, p2p_address: net::SocketAddr) -> (Context, Handle, Receiver<SignedTransaction>) { // should blockchain and mp have & infront here?
+pub fn new(mempool: &Arc<Mutex<Mempool>>, blockchain: &Arc<Mutex<Blockchain>>, p2p_address: net::SocketAddr, signing_keys: Option<Vec<Ed25519KeyPair>>) -> (Context, Handle, Receiver<SignedTransaction>) { // should blockchain and mp have & infront here?
     let (signal_chan_sender, signal_chan_receiver) = unbounded();
     let (finished_tx_sender, finished_tx_receiver) = unbounded();
     let mempool_clone = Arc::clone(mempool);
@@ -77,7 +77,13 @@ pub fn new(mempool: &Arc<Mutex<Mempool>>, blockchain: &Arc<Mutex<Blockchain>>, p
     let mut my_nodes: Vec<Ed25519KeyPair> = Vec::new();
     let mut node_num: u8;
 
-    if p2p_address == "127.0.0.1:6000".parse::<net::SocketAddr>().unwrap() {
+    if let Some(keys) = signing_keys {
+        // sign with the wallet keys, and also pay to them
+        for key_pair in keys.iter() {
+            all_addr.push(Address::from_public_key_bytes(key_pair.public_key().as_ref()));
+        }
+        my_nodes = keys;
+    } else if p2p_address == "127.0.0.1:6000".parse::<net::SocketAddr>().unwrap() {
         
         for i in 0..3{
             let kp_seed = &[i;32];
@@ -237,10 +243,11 @@ impl Context {
                     let go_val = sender_balance/20;
                     // picking a random existing address to send it to
                     let mut rng = rand::thread_rng();
-                    let rand_index: usize = rng.gen_range(0..9);
+                    let rand_index: usize = rng.gen_range(0..self.all_adresses.len());
                     let r_addy = self.all_adresses[rand_index].clone();
                     // creating the signed transaction
-                    let transact = Transaction::new(sender_address, r_addy, go_val, go_nonce);
+                    let chain_id = self.blockchain.lock().unwrap().chain_id;
+                    let transact = Transaction::new(sender_address, r_addy, go_val, go_nonce, chain_id);
                     let tx_c = transact.clone();
                     let signat = sign(&transact,&key_pair); // might need to clone here!!!
                     let signed_tx = SignedTransaction::new(tx_c, signat.as_ref().to_vec(), produced_public_key);
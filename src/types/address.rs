@@ -1,4 +1,6 @@
 use serde::{Serialize, Deserialize};
+use super::encoding::encode_bytes;
+use super::transaction::{SignatureScheme, MAX_MULTISIG_KEYS};
 
 
 // 20-byte address
@@ -22,21 +24,122 @@ impl std::convert::From<[u8; 20]> for Address {
 
 
 
+/// Version byte prefixed to the address in its text encoding
+pub const ADDRESS_VERSION: u8 = 0x00;
+
+const BASE58_ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// First 4 bytes of SHA256(SHA256(payload))
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let first = digest::digest(&digest::SHA256, payload);
+    let second = digest::digest(&digest::SHA256, first.as_ref());
+    let mut output: [u8; 4] = [0; 4];
+    output.copy_from_slice(&second.as_ref()[..4]);
+    output
+}
+
+fn base58_encode(input: &[u8]) -> String {
+    // base 58 digits, least significant first
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in input {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    // every leading zero byte is written as a leading '1'
+    let zeros = input.iter().take_while(|b| **b == 0).count();
+    let mut output = String::new();
+    for _ in 0..zeros {
+        output.push('1');
+    }
+    for digit in digits.iter().rev() {
+        output.push(BASE58_ALPHABET[*digit as usize] as char);
+    }
+    output
+}
+
+fn base58_decode(input: &str) -> Result<Vec<u8>, AddressParseError> {
+    // bytes, least significant first
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in input.bytes() {
+        let mut carry = match BASE58_ALPHABET.iter().position(|a| *a == c) {
+            Some(v) => v as u32,
+            None => return Err(AddressParseError::InvalidCharacter(c as char)),
+        };
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+    let zeros = input.bytes().take_while(|c| *c == b'1').count();
+    let mut output: Vec<u8> = vec![0; zeros];
+    output.extend(bytes.iter().rev());
+    Ok(output)
+}
+
+/// Error returned when parsing an address from its Base58Check text encoding
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddressParseError {
+    InvalidCharacter(char),
+    InvalidLength(usize),
+    InvalidChecksum,
+    WrongVersion(u8),
+}
+
+impl std::fmt::Display for AddressParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AddressParseError::InvalidCharacter(c) => write!(f, "invalid base58 character '{}'", c),
+            AddressParseError::InvalidLength(len) => write!(f, "expected 25 decoded bytes, got {}", len),
+            AddressParseError::InvalidChecksum => write!(f, "checksum mismatch"),
+            AddressParseError::WrongVersion(v) => write!(f, "unexpected address version {}", v),
+        }
+    }
+}
+
+impl std::error::Error for AddressParseError {}
+
+// Base58Check: version byte, the 20 address bytes, then a 4-byte checksum
 impl std::fmt::Display for Address {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let start = if let Some(precision) = f.precision() {
-            if precision >= 40 {
-                0
-            } else {
-                20 - precision / 2
-            }
-        } else {
-            0
-        };
-        for byte_idx in start..20 {
-            write!(f, "{:>02x}", &self.0[byte_idx])?;
+        let mut payload: Vec<u8> = vec![ADDRESS_VERSION];
+        payload.extend_from_slice(&self.0);
+        let check = checksum(&payload);
+        payload.extend_from_slice(&check);
+        write!(f, "{}", base58_encode(&payload))
+    }
+}
+
+impl std::str::FromStr for Address {
+    type Err = AddressParseError;
+
+    fn from_str(s: &str) -> Result<Address, AddressParseError> {
+        let decoded = base58_decode(s)?;
+        if decoded.len() != 25 {
+            return Err(AddressParseError::InvalidLength(decoded.len()));
         }
-        Ok(())
+        let (payload, check) = decoded.split_at(21);
+        if checksum(payload) != check {
+            return Err(AddressParseError::InvalidChecksum);
+        }
+        if payload[0] != ADDRESS_VERSION {
+            return Err(AddressParseError::WrongVersion(payload[0]));
+        }
+        let mut buffer: [u8; 20] = [0; 20];
+        buffer.copy_from_slice(&payload[1..]);
+        Ok(Address(buffer))
     }
 }
 
@@ -54,7 +157,7 @@ impl std::fmt::Debug for Address {
 // how long can bytes be? Do I need to perform multiple hashes if the public key is too long?
 // -> I think bytes can be 768 bits and digest output will be 265 bits
 // 
-use ring::{digest};
+use ring::{digest, signature};
 impl Address {
     pub fn from_public_key_bytes(bytes: &[u8]) -> Address {
         let big_hash = digest::digest(&digest::SHA256, bytes);
@@ -70,12 +173,53 @@ impl Address {
 
 
     }
+
+    /// Address of a script account: the hash of the script behind a tag, like a multisig address
+    pub fn from_script(script: &[u8]) -> Address {
+        let preimage = [b"script".as_ref(), script].concat();
+        Address::from_public_key_bytes(&preimage)
+    }
+
+    /// Address of a single-key account using `scheme`. Ed25519 keys keep the plain hash of the key;
+    /// other schemes hash the key behind a scheme tag so addresses of different schemes never collide.
+    pub fn from_scheme_public_key(scheme: SignatureScheme, bytes: &[u8]) -> Address {
+        match scheme {
+            SignatureScheme::Ed25519 => Address::from_public_key_bytes(bytes),
+            SignatureScheme::EcdsaP256 => {
+                let mut preimage: Vec<u8> = b"p256".to_vec();
+                preimage.extend_from_slice(bytes);
+                Address::from_public_key_bytes(&preimage)
+            }
+        }
+    }
+
+    /// Address of an M-of-N multisignature account: the same hash as above, taken over the
+    /// threshold, the key count and every length-prefixed public key in order. `None` unless the
+    /// threshold is between 1 and the key count, and the keys are at most `MAX_MULTISIG_KEYS`
+    /// distinct Ed25519 public keys.
+    pub fn from_multisig(threshold: u8, public_keys: &[Vec<u8>]) -> Option<Address> {
+        if threshold == 0 || threshold as usize > public_keys.len() || public_keys.len() > MAX_MULTISIG_KEYS {
+            return None;
+        }
+        for (i, key) in public_keys.iter().enumerate() {
+            if key.len() != signature::ED25519_PUBLIC_KEY_LEN || public_keys[..i].contains(key) {
+                return None;
+            }
+        }
+        let mut preimage: Vec<u8> = b"multisig".to_vec();
+        preimage.push(threshold);
+        preimage.push(public_keys.len() as u8);
+        for key in public_keys {
+            encode_bytes(key, &mut preimage);
+        }
+        Some(Address::from_public_key_bytes(&preimage))
+    }
 }
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
 
 #[cfg(test)]
 mod test {
-    use super::Address;
+    use super::{Address, AddressParseError};
 
     #[test]
     fn from_a_test_key() {
@@ -87,6 +231,19 @@ mod test {
         // "0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d0a0b0c0d0e0f0e0d"
         // take the last 20 bytes, we get "1851a0eae0060a132cf0f64a0ffaea248de6cba0"
     }
+
+    #[test]
+    fn text_encoding() {
+        let addr: Address = hex!("1851a0eae0060a132cf0f64a0ffaea248de6cba0").into();
+        assert_eq!(addr.to_string(), "13Db1Fvo9P3TdoJDSGyx7D5oNofvD21dTQ");
+        assert_eq!("13Db1Fvo9P3TdoJDSGyx7D5oNofvD21dTQ".parse::<Address>(), Ok(addr));
+        let zero = Address::default();
+        assert_eq!(zero.to_string().parse::<Address>(), Ok(zero));
+        // a single mistyped character is caught by the checksum
+        assert_eq!("13Db1Fvo9P3TdoJDSGyx7D5oNofvD21dTR".parse::<Address>(), Err(AddressParseError::InvalidChecksum));
+        assert_eq!("13Db1Fvo9P3TdoJDSGyx7D5oNofvD21d0Q".parse::<Address>(), Err(AddressParseError::InvalidCharacter('0')));
+        assert!("13Db1Fvo9P3TdoJDSGyx7D5oNofvD21dT".parse::<Address>().is_err());
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
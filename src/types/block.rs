@@ -3,7 +3,7 @@ use rand;
 use crate::types::hash::{H256, Hashable};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use super::{merkle::MerkleTree, transaction::SignedTransaction};
+use super::{encoding::{encode_bytes, Encode}, merkle::MerkleTree, transaction::SignedTransaction};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 
@@ -17,21 +17,61 @@ pub struct Block {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Header{
 
+    pub version: u32,
     pub parent: H256,
     pub nonce: u32,
+    // rolled by the miner once every nonce has been tried, so no header is hashed twice
+    pub extra_nonce: u64,
     pub difficulty: H256,
     pub timestamp: u128,
     pub merkle_root: H256,
+    // written by the consensus engine over the bare hash; empty under proof of work
+    pub seal: Vec<u8>,
 } 
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Content(pub Vec<SignedTransaction>);
 
+/// Maximum serialized size of a block's content (its transactions, memos included), in bytes
+pub const MAX_BLOCK_SIZE: u64 = 16384;
+
+/// Header version written by this node; blocks with any other version are rejected
+pub const HEADER_VERSION: u32 = 1;
+
+/// Byte offset of the nonce in the canonical encoding of a header
+pub const NONCE_OFFSET: usize = 36;
+
+impl Header {
+    /// Every field but the seal, in encoding order
+    fn encode_bare(&self, out: &mut Vec<u8>) {
+        self.version.encode(out);
+        self.parent.encode(out);
+        self.nonce.encode(out);
+        self.extra_nonce.encode(out);
+        self.difficulty.encode(out);
+        self.timestamp.encode(out);
+        self.merkle_root.encode(out);
+    }
+
+    /// Hash of the header without its seal, which is what a seal signs
+    pub fn bare_hash(&self) -> H256 {
+        let mut out: Vec<u8> = Vec::new();
+        self.encode_bare(&mut out);
+        ring::digest::digest(&ring::digest::SHA256, &out).into()
+    }
+}
+
+impl Encode for Header {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.encode_bare(out);
+        encode_bytes(&self.seal, out);
+    }
+}
+
 impl Hashable for Header{
     fn hash(&self) -> H256 {
-        let serial_signed = serde_json::to_string(self); 
-        ring::digest::digest(&ring::digest::SHA256, serial_signed.unwrap().as_bytes()).into()
+        ring::digest::digest(&ring::digest::SHA256, &self.encoded()).into()
     }
 }
 
@@ -63,6 +103,11 @@ impl Block {
         let Content(stx) = self.content.clone();
         stx
     }
+
+    /// Serialized size of the block content, checked against `MAX_BLOCK_SIZE`
+    pub fn content_size(&self) -> u64 {
+        bincode::serialized_size(&self.content).unwrap()
+    }
 }
 
 pub fn generate_random_block_1(parent: &H256) -> Block {
@@ -71,7 +116,7 @@ pub fn generate_random_block_1(parent: &H256) -> Block {
     let timy = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
     let empty: Vec<H256> = Vec::new();
     let merkly = MerkleTree::new(&empty).root();
-    let heady= Header{parent: *parent, nonce: noncy, difficulty: dify, timestamp: timy, merkle_root: merkly};
+    let heady= Header{version: HEADER_VERSION, parent: *parent, nonce: noncy, extra_nonce: 0, difficulty: dify, timestamp: timy, merkle_root: merkly, seal: Vec::new()};
     let vec:Vec<SignedTransaction> = Vec::new();
     let no_content = Content(vec);
     let lev: u64 = 0;
@@ -86,7 +131,7 @@ pub fn generate_block(parent: &H256, difficulty: &H256, signed_transactions: &Ve
     let timy = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
     let content_duplicate = signed_transactions.clone();
     let merkly = MerkleTree::new(&content_duplicate).root();
-    let heady= Header{parent: *parent, nonce: noncy, difficulty: *difficulty, timestamp: timy, merkle_root: merkly};
+    let heady= Header{version: HEADER_VERSION, parent: *parent, nonce: noncy, extra_nonce: 0, difficulty: *difficulty, timestamp: timy, merkle_root: merkly, seal: Vec::new()};
     let contenty = Content(signed_transactions.clone());
     let block = Block{header: heady, content: contenty};
     block
@@ -101,7 +146,7 @@ pub fn generate_random_block(parent: &H256) -> Block {
     let timy = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis();
     let empty: Vec<H256> = Vec::new();
     let merkly = MerkleTree::new(&empty).root();
-    let heady= Header{parent: *parent, nonce: noncy, difficulty: dify, timestamp: timy, merkle_root: merkly};
+    let heady= Header{version: HEADER_VERSION, parent: *parent, nonce: noncy, extra_nonce: 0, difficulty: dify, timestamp: timy, merkle_root: merkly, seal: Vec::new()};
     let vec:Vec<SignedTransaction> = Vec::new();
     let no_content = Content(vec);
     let lev: u64 = 0;
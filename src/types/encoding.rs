@@ -0,0 +1,96 @@
+//! Canonical binary encoding, used for every hash and signature.
+//!
+//! The rules are simple enough to reproduce in any language:
+//! - integers are fixed width, big endian (`u8`, `u32`, `u64`, `u128`)
+//! - `H256` and `Address` are their raw 32 and 20 bytes
+//! - byte strings and lists are a `u32` element count followed by the elements
+//! - `Option`s are a `0` byte for `None`, or a `1` byte followed by the value
+//! - enums are a one-byte variant tag followed by the variant's fields
+//! - struct fields are written in declaration order, with no padding or names
+//!
+//! `Transaction` and `SignedTransaction` start with `ENCODING_VERSION`; `Header` carries its own `version` field.
+//! The wire format (bincode) is separate and never hashed.
+
+use super::address::Address;
+use super::hash::H256;
+
+/// Version byte at the start of the encoding of transactions
+pub const ENCODING_VERSION: u8 = 1;
+
+pub trait Encode {
+    /// Append the canonical encoding of `self` to `out`
+    fn encode(&self, out: &mut Vec<u8>);
+
+    fn encoded(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+}
+
+impl Encode for u8 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(*self);
+    }
+}
+
+impl Encode for u32 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl Encode for u64 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl Encode for u128 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_be_bytes());
+    }
+}
+
+impl Encode for H256 {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(self.as_ref());
+    }
+}
+
+impl Encode for Address {
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.0);
+    }
+}
+
+impl<A: Encode, B: Encode> Encode for (A, B) {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.0.encode(out);
+        self.1.encode(out);
+    }
+}
+
+/// Length-prefixed byte string
+pub fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    (bytes.len() as u32).encode(out);
+    out.extend_from_slice(bytes);
+}
+
+/// Length-prefixed list
+pub fn encode_list<T: Encode>(items: &[T], out: &mut Vec<u8>) {
+    (items.len() as u32).encode(out);
+    for item in items {
+        item.encode(out);
+    }
+}
+
+pub fn encode_option<T: Encode>(item: &Option<T>, out: &mut Vec<u8>) {
+    match item {
+        None => out.push(0),
+        Some(v) => {
+            out.push(1);
+            v.encode(out);
+        }
+    }
+}
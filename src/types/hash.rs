@@ -109,6 +109,197 @@ impl PartialOrd for H256 {
     }
 }
 
+impl std::convert::From<u64> for H256 {
+    fn from(input: u64) -> H256 {
+        H256::from_limbs([0, 0, 0, input])
+    }
+}
+
+/// 256-bit unsigned integer arithmetic, treating the hash as a big endian number (e.g. a difficulty target)
+impl H256 {
+    // four u64 limbs, most significant first
+    fn to_limbs(self) -> [u64; 4] {
+        let mut limbs: [u64; 4] = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = u64::from_be_bytes(self.0[i * 8..i * 8 + 8].try_into().unwrap());
+        }
+        limbs
+    }
+
+    fn from_limbs(limbs: [u64; 4]) -> H256 {
+        let mut buffer: [u8; 32] = [0; 32];
+        for (i, limb) in limbs.iter().enumerate() {
+            buffer[i * 8..i * 8 + 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        H256(buffer)
+    }
+
+    // bit `i`, counting from the least significant bit
+    fn bit(&self, i: usize) -> bool {
+        (self.0[31 - i / 8] >> (i % 8)) & 1 == 1
+    }
+
+    /// The largest 256-bit number, 2^256 - 1
+    pub fn max_value() -> H256 {
+        H256([0xff; 32])
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 32]
+    }
+
+    fn overflowing_add(&self, other: &H256) -> (H256, bool) {
+        let (a, b) = (self.to_limbs(), other.to_limbs());
+        let mut output: [u64; 4] = [0; 4];
+        let mut carry = false;
+        for i in (0..4).rev() {
+            let (sum, c1) = a[i].overflowing_add(b[i]);
+            let (sum, c2) = sum.overflowing_add(carry as u64);
+            output[i] = sum;
+            carry = c1 || c2;
+        }
+        (H256::from_limbs(output), carry)
+    }
+
+    fn overflowing_sub(&self, other: &H256) -> (H256, bool) {
+        let (a, b) = (self.to_limbs(), other.to_limbs());
+        let mut output: [u64; 4] = [0; 4];
+        let mut borrow = false;
+        for i in (0..4).rev() {
+            let (diff, b1) = a[i].overflowing_sub(b[i]);
+            let (diff, b2) = diff.overflowing_sub(borrow as u64);
+            output[i] = diff;
+            borrow = b1 || b2;
+        }
+        (H256::from_limbs(output), borrow)
+    }
+
+    // shift left by one bit, returning the bit shifted out
+    fn shl1(&self) -> (H256, bool) {
+        let mut output: [u8; 32] = [0; 32];
+        for (i, byte) in output.iter_mut().enumerate() {
+            *byte = self.0[i] << 1 | if i < 31 { self.0[i + 1] >> 7 } else { 0 };
+        }
+        (H256(output), self.0[0] >> 7 == 1)
+    }
+
+    pub fn checked_add(&self, other: &H256) -> Option<H256> {
+        match self.overflowing_add(other) {
+            (sum, false) => Some(sum),
+            _ => None,
+        }
+    }
+
+    pub fn checked_sub(&self, other: &H256) -> Option<H256> {
+        match self.overflowing_sub(other) {
+            (diff, false) => Some(diff),
+            _ => None,
+        }
+    }
+
+    pub fn checked_mul_u64(&self, rhs: u64) -> Option<H256> {
+        let limbs = self.to_limbs();
+        let mut output: [u64; 4] = [0; 4];
+        let mut carry: u128 = 0;
+        for i in (0..4).rev() {
+            let product = limbs[i] as u128 * rhs as u128 + carry;
+            output[i] = product as u64;
+            carry = product >> 64;
+        }
+        if carry == 0 { Some(H256::from_limbs(output)) } else { None }
+    }
+
+    /// Quotient and remainder of division by `rhs`. Panics if `rhs` is zero.
+    pub fn div_rem_u64(&self, rhs: u64) -> (H256, u64) {
+        assert!(rhs != 0, "division by zero");
+        let limbs = self.to_limbs();
+        let mut output: [u64; 4] = [0; 4];
+        let mut remainder: u128 = 0;
+        for i in 0..4 {
+            let dividend = remainder << 64 | limbs[i] as u128;
+            output[i] = (dividend / rhs as u128) as u64;
+            remainder = dividend % rhs as u128;
+        }
+        (H256::from_limbs(output), remainder as u64)
+    }
+
+    /// Integer division by another 256-bit number, `None` if `rhs` is zero
+    pub fn checked_div(&self, rhs: &H256) -> Option<H256> {
+        if rhs.is_zero() {
+            return None;
+        }
+        let mut quotient: [u8; 32] = [0; 32];
+        let mut remainder = H256::default();
+        for i in (0..256).rev() {
+            let (shifted, carry) = remainder.shl1();
+            remainder = shifted;
+            remainder.0[31] |= self.bit(i) as u8;
+            // with a carry the true remainder is above 2^256 and therefore above rhs
+            if carry || remainder >= *rhs {
+                remainder = remainder.overflowing_sub(rhs).0;
+                quotient[31 - i / 8] |= 1 << (i % 8);
+            }
+        }
+        Some(H256(quotient))
+    }
+
+    /// Expected number of hashes to find one at or below this target: 2^256 / (target + 1).
+    /// A zero target saturates at `max_value()`.
+    pub fn work(&self) -> H256 {
+        // 2^256 / (t + 1) == (2^256 - 1 - t) / (t + 1) + 1, which stays within 256 bits
+        let plus_one = match self.checked_add(&H256::from(1)) {
+            Some(v) => v,
+            None => return H256::from(1),
+        };
+        let complement = H256::max_value().overflowing_sub(self).0;
+        complement.checked_div(&plus_one).unwrap().checked_add(&H256::from(1)).unwrap_or_else(H256::max_value)
+    }
+
+    /// Bitcoin-style compact encoding: one byte of length followed by the 3 most significant bytes.
+    /// Precision below those 3 bytes is lost.
+    pub fn to_compact(&self) -> u32 {
+        let mut size = self.0.iter().position(|b| *b != 0).map_or(0, |zeros| 32 - zeros);
+        let mut mantissa: u32 = 0;
+        for i in 0..3 {
+            mantissa <<= 8;
+            if i < size {
+                mantissa |= self.0[32 - size + i] as u32;
+            }
+        }
+        // the top mantissa bit is a sign bit, so move to the next length instead of setting it
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        (size as u32) << 24 | mantissa
+    }
+
+    /// Decode the compact encoding, `None` for negative or out of range values
+    pub fn from_compact(bits: u32) -> Option<H256> {
+        let size = (bits >> 24) as usize;
+        let mantissa = bits & 0x007f_ffff;
+        if mantissa == 0 {
+            return Some(H256::default());
+        }
+        if bits & 0x0080_0000 != 0 {
+            return None;
+        }
+        let mantissa_bytes = mantissa.to_be_bytes();
+        let mut buffer: [u8; 32] = [0; 32];
+        for (i, byte) in mantissa_bytes[1..].iter().enumerate() {
+            // byte i of the mantissa lands at position `size - 1 - i` counting from the least significant byte
+            // bytes below position 0 are shifted out; bytes above the 32nd do not fit
+            let position = size as isize - 1 - i as isize;
+            if (0..32).contains(&position) {
+                buffer[31 - position as usize] = *byte;
+            } else if position >= 32 && *byte != 0 {
+                return None;
+            }
+        }
+        Some(H256(buffer))
+    }
+}
+
 #[cfg(any(test, test_utilities))]
 pub fn generate_random_hash() -> H256 {
     let mut rng = rand::thread_rng();
@@ -116,4 +307,70 @@ pub fn generate_random_hash() -> H256 {
     let mut raw_bytes = [0; 32];
     raw_bytes.copy_from_slice(&random_bytes);
     (&raw_bytes).into()
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    fn from_u128(v: u128) -> H256 {
+        let mut buffer: [u8; 32] = [0; 32];
+        buffer[16..].copy_from_slice(&v.to_be_bytes());
+        H256(buffer)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = from_u128(0x1234_5678_9abc_def0_1122_3344);
+        assert_eq!(a.checked_mul_u64(1000).unwrap(), from_u128(0x1234_5678_9abc_def0_1122_3344 * 1000));
+        assert_eq!(a.div_rem_u64(1000), (from_u128(0x1234_5678_9abc_def0_1122_3344 / 1000), (0x1234_5678_9abc_def0_1122_3344u128 % 1000) as u64));
+        assert_eq!(a.checked_div(&from_u128(12345)).unwrap(), from_u128(0x1234_5678_9abc_def0_1122_3344 / 12345));
+        assert!(H256::max_value().checked_mul_u64(2).is_none());
+        assert!(H256::max_value().checked_add(&H256::from(1)).is_none());
+        assert!(H256::from(1).checked_sub(&H256::from(2)).is_none());
+        assert!(a.checked_div(&H256::default()).is_none());
+
+        let random = generate_random_hash();
+        let (quotient, remainder) = random.div_rem_u64(7);
+        assert_eq!(quotient.checked_mul_u64(7).unwrap().checked_add(&H256::from(remainder)).unwrap(), random);
+        assert_eq!(random.checked_div(&H256::from(7)).unwrap(), quotient);
+        assert_eq!(H256::max_value().checked_div(&H256::max_value()).unwrap(), H256::from(1));
+    }
+
+    #[test]
+    fn work() {
+        assert_eq!(H256::max_value().work(), H256::from(1));
+        // target 2^255 - 1: half of all hashes are below it
+        let mut half = H256::max_value();
+        half.0[0] = 0x7f;
+        assert_eq!(half.work(), H256::from(2));
+        // leading 16 zero bits
+        let mut target = H256::max_value();
+        target.0[0] = 0;
+        target.0[1] = 0;
+        assert_eq!(target.work(), H256::from(65536));
+        assert_eq!(H256::default().work(), H256::max_value());
+    }
+
+    #[test]
+    fn compact() {
+        let genesis_target = H256(hex!("00000000ffff0000000000000000000000000000000000000000000000000000"));
+        assert_eq!(H256::from_compact(0x1d00ffff).unwrap(), genesis_target);
+        assert_eq!(genesis_target.to_compact(), 0x1d00ffff);
+        let target = H256(hex!("00000000000404cb000000000000000000000000000000000000000000000000"));
+        assert_eq!(H256::from_compact(0x1b0404cb).unwrap(), target);
+        assert_eq!(target.to_compact(), 0x1b0404cb);
+
+        assert_eq!(H256::from_compact(0x01123456).unwrap(), H256::from(0x12));
+        assert_eq!(H256::from(0x80).to_compact(), 0x02008000);
+        assert_eq!(H256::from_compact(0x02008000).unwrap(), H256::from(0x80));
+        assert_eq!(H256::default().to_compact(), 0);
+        assert_eq!(H256::from_compact(0).unwrap(), H256::default());
+        // negative and overflowing encodings
+        assert!(H256::from_compact(0x04923456).is_none());
+        assert!(H256::from_compact(0x23000001).is_none());
+        assert!(H256::from_compact(0x2101ffff).is_none());
+        assert_eq!(H256::from_compact(0x2100ffff).unwrap().0[..3], [0xff, 0xff, 0]);
+        assert_eq!(H256::from_compact(0x20123456).unwrap().to_compact(), 0x20123456);
+    }
+}
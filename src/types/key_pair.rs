@@ -1,5 +1,5 @@
 use ring::rand;
-use ring::signature::Ed25519KeyPair;
+use ring::signature::{Ed25519KeyPair, EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
 
 /// Generate a random key pair.
 pub fn random() -> Ed25519KeyPair {
@@ -7,3 +7,10 @@ pub fn random() -> Ed25519KeyPair {
     let pkcs8_bytes = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
     Ed25519KeyPair::from_pkcs8(pkcs8_bytes.as_ref().into()).unwrap()
 }
+
+/// Generate a random ECDSA P-256 key pair.
+pub fn random_p256() -> EcdsaKeyPair {
+    let rng = rand::SystemRandom::new();
+    let pkcs8_bytes = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+    EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8_bytes.as_ref()).unwrap()
+}
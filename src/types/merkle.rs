@@ -1,10 +1,197 @@
+use super::address::Address;
 use super::hash::{Hashable, H256};
+use serde::{Serialize, Deserialize};
+use std::collections::{BTreeMap, HashMap};
 
 /// A Merkle tree.
 #[derive(Debug, Default)]
 pub struct MerkleTree {
     tree_vector: Vec<Vec<H256>>,
     levels: u8, //the highest level of Merkel Tree, (index starts at 0)
+    leaf_size: usize, // number of data items, before padding
+}
+
+/// Proof that the leaf at `index` is in a tree of `leaf_size` leaves: the sibling hashes from the leaf up to the root.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub leaf_size: usize,
+    pub siblings: Vec<H256>,
+}
+
+/// Proof for several leaves at once. `hashes` only holds the siblings that cannot be computed
+/// from the proven leaves themselves, in the order `verify_multi` consumes them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MerkleMultiProof {
+    pub indices: Vec<usize>,
+    pub leaf_size: usize,
+    pub hashes: Vec<H256>,
+}
+
+fn hash_two(left: &H256, right: &H256) -> H256 {
+    let both = [left.as_ref(), right.as_ref()].concat();
+    digest::digest(&digest::SHA256, &both).into()
+}
+
+/// Number of nodes in each level below the root (before padding) of a tree with `leaf_size` leaves
+fn level_widths(leaf_size: usize) -> Vec<usize> {
+    let mut widths: Vec<usize> = Vec::new();
+    let mut width = leaf_size;
+    while width >= 2 {
+        widths.push(width);
+        width = width.div_ceil(2);
+    }
+    widths
+}
+
+/// Depth of the sparse Merkle tree, one level per bit of an `Address`
+pub const SPARSE_DEPTH: usize = 160;
+
+// bit `i` of an address, most significant bit first; bit `i` picks the branch below depth `i`
+fn address_bit(address: &Address, i: usize) -> bool {
+    (address.0[i / 8] >> (7 - i % 8)) & 1 == 1
+}
+
+fn flip_bit(address: &Address, i: usize) -> Address {
+    let mut output = *address;
+    output.0[i / 8] ^= 1 << (7 - i % 8);
+    output
+}
+
+// the first `depth` bits of an address, the rest set to zero
+fn address_prefix(address: &Address, depth: usize) -> Address {
+    let mut output = *address;
+    for i in depth..SPARSE_DEPTH {
+        output.0[i / 8] &= !(1 << (7 - i % 8));
+    }
+    output
+}
+
+/// Hashes of empty subtrees by depth: index 0 is the root of an empty tree, `SPARSE_DEPTH` an empty leaf (all zeros)
+fn empty_hashes() -> Vec<H256> {
+    let mut empty = vec![H256::default(); SPARSE_DEPTH + 1];
+    for depth in (0..SPARSE_DEPTH).rev() {
+        empty[depth] = hash_two(&empty[depth + 1], &empty[depth + 1]);
+    }
+    empty
+}
+
+/// A sparse Merkle tree with one leaf per possible `Address`. Leaves are 32-byte values, and an
+/// all-zero leaf means the address is absent. Only non-empty nodes are stored.
+#[derive(Debug, Clone)]
+pub struct SparseMerkleTree {
+    leaves: HashMap<Address, H256>,
+    // non-empty nodes, keyed by depth and the address prefix of that many bits
+    nodes: HashMap<(usize, Address), H256>,
+    empty: Vec<H256>,
+}
+
+/// Proof of the leaf at an address. Bit `i` of `bitmap` (same bit order as the address) is set when the
+/// sibling below depth `i` is not an empty subtree; only those siblings are listed, from the leaf upwards.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SparseMerkleProof {
+    pub bitmap: Address,
+    pub siblings: Vec<H256>,
+}
+
+impl Default for SparseMerkleTree {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SparseMerkleTree {
+    pub fn new() -> Self {
+        SparseMerkleTree { leaves: HashMap::new(), nodes: HashMap::new(), empty: empty_hashes() }
+    }
+
+    fn node(&self, depth: usize, prefix: &Address) -> H256 {
+        match self.nodes.get(&(depth, *prefix)) {
+            Some(hash) => *hash,
+            None => self.empty[depth],
+        }
+    }
+
+    /// Set the leaf at `address`, updating the path to the root. Inserting the zero value removes the leaf.
+    pub fn insert(&mut self, address: Address, value: H256) {
+        if value == H256::default() {
+            self.leaves.remove(&address);
+        } else {
+            self.leaves.insert(address, value);
+        }
+        let mut hash = value;
+        for depth in (0..=SPARSE_DEPTH).rev() {
+            let prefix = address_prefix(&address, depth);
+            if hash == self.empty[depth] {
+                self.nodes.remove(&(depth, prefix));
+            } else {
+                self.nodes.insert((depth, prefix), hash);
+            }
+            if depth == 0 {
+                break;
+            }
+            let sibling = self.node(depth, &flip_bit(&prefix, depth - 1));
+            hash = if address_bit(&address, depth - 1) { hash_two(&sibling, &hash) } else { hash_two(&hash, &sibling) };
+        }
+    }
+
+    pub fn remove(&mut self, address: &Address) {
+        self.insert(*address, H256::default());
+    }
+
+    pub fn get(&self, address: &Address) -> Option<H256> {
+        self.leaves.get(address).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn root(&self) -> H256 {
+        self.node(0, &Address::default())
+    }
+
+    /// Proof of the current leaf at `address`: a membership proof if it is set, a non-membership proof otherwise
+    pub fn proof(&self, address: &Address) -> SparseMerkleProof {
+        let mut bitmap = Address::default();
+        let mut siblings: Vec<H256> = Vec::new();
+        for depth in (1..=SPARSE_DEPTH).rev() {
+            let sibling_prefix = flip_bit(&address_prefix(address, depth), depth - 1);
+            if let Some(hash) = self.nodes.get(&(depth, sibling_prefix)) {
+                bitmap = flip_bit(&bitmap, depth - 1);
+                siblings.push(*hash);
+            }
+        }
+        SparseMerkleProof { bitmap, siblings }
+    }
+}
+
+/// Verify a sparse Merkle proof against `root`. `value` is the leaf claimed at `address`, or `None` to
+/// check that the address is absent.
+pub fn verify_sparse(root: &H256, address: &Address, value: Option<&H256>, proof: &SparseMerkleProof) -> bool {
+    let empty = empty_hashes();
+    let mut hash = match value {
+        Some(v) if *v == H256::default() => return false,
+        Some(v) => *v,
+        None => H256::default(),
+    };
+    let mut siblings = proof.siblings.iter();
+    for depth in (1..=SPARSE_DEPTH).rev() {
+        let sibling = if address_bit(&proof.bitmap, depth - 1) {
+            match siblings.next() {
+                Some(h) => *h,
+                None => return false,
+            }
+        } else {
+            empty[depth]
+        };
+        hash = if address_bit(address, depth - 1) { hash_two(&sibling, &hash) } else { hash_two(&hash, &sibling) };
+    }
+    siblings.next().is_none() && hash == *root
 }
 
 use ring::{digest};
@@ -78,7 +265,7 @@ impl MerkleTree {
             }
             output.push(out_push2);
 
-        MerkleTree { tree_vector: output, levels: lev }
+        MerkleTree { tree_vector: output, levels: lev, leaf_size: data.len() }
 
         }
     
@@ -107,14 +294,60 @@ impl MerkleTree {
         output
     }
 
+    pub fn leaf_size(&self) -> usize {
+        self.leaf_size
+    }
+
+    /// Returns the Merkle Proof of data at index i, together with the index and the leaf count
+    pub fn merkle_proof(&self, index: usize) -> MerkleProof {
+        MerkleProof { index, leaf_size: self.leaf_size, siblings: self.proof(index) }
+    }
+
+    /// Returns one proof for the data at all of `indices`, sharing sibling hashes between them
+    pub fn multi_proof(&self, indices: &[usize]) -> MerkleMultiProof {
+        let mut known: Vec<usize> = indices.to_vec();
+        known.sort_unstable();
+        known.dedup();
+        let proven = known.clone();
+        let mut hashes: Vec<H256> = Vec::new();
+        for (level, width) in level_widths(self.leaf_size).into_iter().enumerate() {
+            for position in known.iter() {
+                let sibling = position ^ 1;
+                // siblings that are proven themselves, or padding copies of the node, are not needed
+                if !known.contains(&sibling) && sibling < width {
+                    hashes.push(self.tree_vector[level][sibling]);
+                }
+            }
+            known = known.iter().map(|p| p / 2).collect();
+            known.dedup();
+        }
+        MerkleMultiProof { indices: proven, leaf_size: self.leaf_size, hashes }
+    }
+
+}
+
+impl MerkleProof {
+    pub fn verify(&self, root: &H256, datum: &H256) -> bool {
+        verify(root, datum, &self.siblings, self.index, self.leaf_size)
+    }
 }
+
 /// Verify that the datum hash with a vector of proofs will produce the Merkle root. Also need the
-/// index of datum and `leaf_size`, the total number of leaves.
+/// index of datum and `leaf_size`, the total number of leaves. Proofs whose length or padding do
+/// not match a tree of `leaf_size` leaves are rejected.
 pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size: usize) -> bool {
+    let widths = level_widths(leaf_size);
+    if index >= leaf_size || proof.len() != widths.len() {
+        return false;
+    }
     // start with datum, based on index value, hash left or hash right until you get the root
     let mut gen_root: H256 = *datum;
     let mut ind = index;
-    for x in proof{
+    for (x, width) in proof.iter().zip(widths.iter()){
+        // the last node of an odd level is paired with a copy of itself
+        if ind + 1 == *width && width % 2 == 1 && *x != gen_root {
+            return false;
+        }
         if ind%2 == 1{ //right side
             let both = [x.as_ref(), gen_root.as_ref()].concat();
             let both_slice = both.as_slice();
@@ -129,11 +362,48 @@ pub fn verify(root: &H256, datum: &H256, proof: &[H256], index: usize, leaf_size
             let hash_H256 = H256::from(both_hash);
             gen_root = hash_H256;
         }
+        ind /= 2;
     }
 
     gen_root == *root
     
 }
+
+/// Verify a multi-proof, where `data` holds the hashes of the proven leaves in the order of `proof.indices`
+pub fn verify_multi(root: &H256, data: &[H256], proof: &MerkleMultiProof) -> bool {
+    if data.len() != proof.indices.len() || data.is_empty() {
+        return false;
+    }
+    let mut known: BTreeMap<usize, H256> = BTreeMap::new();
+    for (index, datum) in proof.indices.iter().zip(data.iter()) {
+        if *index >= proof.leaf_size || known.insert(*index, *datum).is_some() {
+            return false;
+        }
+    }
+    let mut hashes = proof.hashes.iter();
+    for width in level_widths(proof.leaf_size) {
+        let mut parents: BTreeMap<usize, H256> = BTreeMap::new();
+        for (position, hash) in known.iter() {
+            if parents.contains_key(&(position / 2)) {
+                continue;
+            }
+            let sibling = position ^ 1;
+            let sibling_hash = match known.get(&sibling) {
+                Some(h) => *h,
+                None if sibling >= width => *hash,
+                None => match hashes.next() {
+                    Some(h) => *h,
+                    None => return false,
+                },
+            };
+            let parent = if position % 2 == 0 { hash_two(hash, &sibling_hash) } else { hash_two(&sibling_hash, hash) };
+            parents.insert(position / 2, parent);
+        }
+        known = parents;
+    }
+    // every supplied hash must have been used
+    hashes.next().is_none() && known.len() == 1 && known.get(&0) == Some(root)
+}
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. BEFORE TEST
 
 #[cfg(test)]
@@ -187,6 +457,99 @@ mod tests {
         let proof = merkle_tree.proof(0);
         assert!(verify(&merkle_tree.root(), &input_data[0].hash(), &proof, 0, input_data.len()));
     }
+
+    fn numbered_data(n: u8) -> Vec<H256> {
+        (0..n).map(|i| H256::from([i; 32])).collect()
+    }
+
+    #[test]
+    fn merkle_proof_object() {
+        for n in 1..10 {
+            let input_data = numbered_data(n);
+            let merkle_tree = MerkleTree::new(&input_data);
+            for (i, datum) in input_data.iter().enumerate() {
+                let proof = merkle_tree.merkle_proof(i);
+                assert!(proof.verify(&merkle_tree.root(), &datum.hash()));
+                let serialized = serde_json::to_string(&proof).unwrap();
+                let deserialized: MerkleProof = serde_json::from_str(&serialized).unwrap();
+                assert_eq!(deserialized, proof);
+            }
+        }
+        let input_data = numbered_data(5);
+        let merkle_tree = MerkleTree::new(&input_data);
+        let root = merkle_tree.root();
+        let proof = merkle_tree.proof(4);
+        // an index past the end, or a proof length that does not fit the leaf count
+        assert!(!verify(&root, &input_data[4].hash(), &proof, 5, 5));
+        assert!(!verify(&root, &input_data[4].hash(), &proof, 4, 2));
+        assert!(!verify(&root, &input_data[4].hash(), &proof[1..], 4, 5));
+        // leaf 2 of 4 claimed as the last leaf of 3 must have been paired with a copy of itself
+        let input_data = numbered_data(4);
+        let merkle_tree = MerkleTree::new(&input_data);
+        assert!(merkle_tree.merkle_proof(2).verify(&merkle_tree.root(), &input_data[2].hash()));
+        assert!(!verify(&merkle_tree.root(), &input_data[2].hash(), &merkle_tree.proof(2), 2, 3));
+    }
+
+    #[test]
+    fn merkle_multi_proof() {
+        for n in 1..10 {
+            let input_data = numbered_data(n);
+            let merkle_tree = MerkleTree::new(&input_data);
+            let root = merkle_tree.root();
+            let subsets: Vec<Vec<usize>> = vec![vec![0], (0..n as usize).collect(), (0..n as usize).step_by(2).collect(), vec![n as usize - 1]];
+            for indices in subsets {
+                let proof = merkle_tree.multi_proof(&indices);
+                let data: Vec<H256> = proof.indices.iter().map(|i| input_data[*i].hash()).collect();
+                assert!(verify_multi(&root, &data, &proof));
+                let mut wrong_data = data.clone();
+                wrong_data[0] = H256::from([255; 32]);
+                assert!(!verify_multi(&root, &wrong_data, &proof));
+            }
+        }
+        // proving every leaf needs no extra hashes; siblings are shared between neighbours
+        let input_data = numbered_data(8);
+        let merkle_tree = MerkleTree::new(&input_data);
+        assert!(merkle_tree.multi_proof(&[0, 1, 2, 3, 4, 5, 6, 7]).hashes.is_empty());
+        assert_eq!(merkle_tree.multi_proof(&[0, 1]).hashes.len(), 2);
+    }
+
+    #[test]
+    fn sparse_merkle_tree() {
+        let mut tree = SparseMerkleTree::new();
+        let empty_root = tree.root();
+        let alice = Address::from([1; 20]);
+        let bob = Address::from([2; 20]);
+        let carol = Address::from([3; 20]);
+        let value = H256::from([7; 32]);
+
+        tree.insert(alice, value);
+        tree.insert(bob, H256::from([8; 32]));
+        let root = tree.root();
+        assert_ne!(root, empty_root);
+        assert!(verify_sparse(&root, &alice, Some(&value), &tree.proof(&alice)));
+        assert!(!verify_sparse(&root, &alice, Some(&H256::from([9; 32])), &tree.proof(&alice)));
+        assert!(!verify_sparse(&root, &alice, None, &tree.proof(&alice)));
+        // carol has never been inserted
+        assert!(verify_sparse(&root, &carol, None, &tree.proof(&carol)));
+        assert!(!verify_sparse(&root, &carol, Some(&value), &tree.proof(&carol)));
+
+        let proof = tree.proof(&bob);
+        let serialized = serde_json::to_string(&proof).unwrap();
+        assert_eq!(serde_json::from_str::<SparseMerkleProof>(&serialized).unwrap(), proof);
+
+        // updating a leaf changes the root; the root does not depend on insertion order
+        tree.insert(alice, H256::from([9; 32]));
+        assert_ne!(tree.root(), root);
+        let mut other = SparseMerkleTree::new();
+        other.insert(bob, H256::from([8; 32]));
+        other.insert(alice, H256::from([9; 32]));
+        assert_eq!(other.root(), tree.root());
+
+        tree.remove(&alice);
+        tree.remove(&bob);
+        assert!(tree.is_empty());
+        assert_eq!(tree.root(), empty_root);
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
@@ -1,6 +1,8 @@
 pub mod address;
 pub mod block;
+pub mod encoding;
 pub mod hash;
 pub mod merkle;
+pub mod script;
 pub mod key_pair;
 pub mod transaction;
\ No newline at end of file
@@ -0,0 +1,327 @@
+//! A small stack language for spending conditions.
+//!
+//! A script account's address is the hash of its script (see `Address::from_script`). To spend, a
+//! transaction reveals the script together with arguments that are pushed on the stack before it runs.
+//! The spend is authorized if the script runs to the end within `MAX_SCRIPT_BUDGET` and leaves a true
+//! value on top of the stack. Stack items are byte strings; a value is false if it is empty or all zeros.
+//! There are no loops or jumps, so every script terminates and its cost is bounded by its length.
+
+use ring::digest;
+use ring::signature;
+
+/// Push an empty (false) value
+pub const OP_FALSE: u8 = 0x00;
+/// Opcodes 0x01 to 0x4b push that many following bytes
+pub const OP_PUSH_MAX: u8 = 0x4b;
+/// Push the number of bytes given by the next byte, followed by those bytes
+pub const OP_PUSHDATA1: u8 = 0x4c;
+/// Push the number of bytes given by the next two bytes (little endian), followed by those bytes
+pub const OP_PUSHDATA2: u8 = 0x4d;
+/// Push 0x01
+pub const OP_TRUE: u8 = 0x51;
+/// Fail unless the top value is true; pops it
+pub const OP_VERIFY: u8 = 0x69;
+pub const OP_DROP: u8 = 0x75;
+pub const OP_DUP: u8 = 0x76;
+pub const OP_SWAP: u8 = 0x7c;
+/// Pop two values, push whether they are equal
+pub const OP_EQUAL: u8 = 0x87;
+pub const OP_EQUALVERIFY: u8 = 0x88;
+pub const OP_NOT: u8 = 0x91;
+pub const OP_BOOLAND: u8 = 0x9a;
+pub const OP_BOOLOR: u8 = 0x9b;
+/// Replace the top value with its SHA256 hash
+pub const OP_SHA256: u8 = 0xa8;
+/// Pop a public key, then a signature; push whether it is a valid Ed25519 signature of the transaction
+pub const OP_CHECKSIG: u8 = 0xac;
+pub const OP_CHECKSIGVERIFY: u8 = 0xad;
+/// Pop a big endian height of at most 8 bytes; fail if the transaction is validated below that height
+pub const OP_CHECKHEIGHT: u8 = 0xb1;
+
+/// Maximum length of a script, in bytes
+pub const MAX_SCRIPT_SIZE: usize = 520;
+/// Maximum size of one stack item, in bytes
+pub const MAX_ITEM_SIZE: usize = 520;
+/// Maximum number of items on the stack, arguments included
+pub const MAX_STACK_SIZE: usize = 100;
+/// Cost units one script run may spend
+pub const MAX_SCRIPT_BUDGET: u32 = 1000;
+
+const OP_COST: u32 = 1;
+const SHA256_COST: u32 = 10;
+const CHECKSIG_COST: u32 = 100;
+
+/// What a script can see of the transaction spending it
+pub struct ScriptContext<'a> {
+    /// the signing message of the transaction (its canonical encoding)
+    pub message: &'a [u8],
+    /// height of the block the transaction would be included in
+    pub height: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScriptError {
+    ScriptTooLarge,
+    ItemTooLarge,
+    BudgetExceeded,
+    StackUnderflow,
+    StackOverflow,
+    /// a push runs past the end of the script
+    Truncated,
+    InvalidOpcode(u8),
+    InvalidHeight,
+    HeightNotReached,
+    VerifyFailed,
+    /// the script finished without a true value on top of the stack
+    Failed,
+}
+
+impl std::fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ScriptError::ScriptTooLarge => write!(f, "script is larger than {} bytes", MAX_SCRIPT_SIZE),
+            ScriptError::ItemTooLarge => write!(f, "stack item is larger than {} bytes", MAX_ITEM_SIZE),
+            ScriptError::BudgetExceeded => write!(f, "script exceeded its budget of {}", MAX_SCRIPT_BUDGET),
+            ScriptError::StackUnderflow => write!(f, "stack underflow"),
+            ScriptError::StackOverflow => write!(f, "more than {} stack items", MAX_STACK_SIZE),
+            ScriptError::Truncated => write!(f, "push past the end of the script"),
+            ScriptError::InvalidOpcode(op) => write!(f, "invalid opcode 0x{:02x}", op),
+            ScriptError::InvalidHeight => write!(f, "height is not a number of at most 8 bytes"),
+            ScriptError::HeightNotReached => write!(f, "height lock not reached"),
+            ScriptError::VerifyFailed => write!(f, "verify failed"),
+            ScriptError::Failed => write!(f, "script did not leave a true value"),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Append an instruction pushing `data` to `script`
+///
+/// Panics if `data` is larger than `MAX_ITEM_SIZE`, since no script could push it.
+pub fn push_data(script: &mut Vec<u8>, data: &[u8]) {
+    assert!(data.len() <= MAX_ITEM_SIZE, "pushed data is larger than {} bytes", MAX_ITEM_SIZE);
+    if data.is_empty() {
+        script.push(OP_FALSE);
+    } else if data.len() <= OP_PUSH_MAX as usize {
+        script.push(data.len() as u8);
+    } else if data.len() <= u8::MAX as usize {
+        script.push(OP_PUSHDATA1);
+        script.push(data.len() as u8);
+    } else {
+        script.push(OP_PUSHDATA2);
+        script.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    }
+    script.extend_from_slice(data);
+}
+
+fn is_true(item: &[u8]) -> bool {
+    item.iter().any(|b| *b != 0)
+}
+
+fn from_bool(value: bool) -> Vec<u8> {
+    if value { vec![1] } else { Vec::new() }
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, ScriptError> {
+    stack.pop().ok_or(ScriptError::StackUnderflow)
+}
+
+fn push(stack: &mut Vec<Vec<u8>>, item: Vec<u8>) -> Result<(), ScriptError> {
+    if item.len() > MAX_ITEM_SIZE {
+        return Err(ScriptError::ItemTooLarge);
+    }
+    if stack.len() >= MAX_STACK_SIZE {
+        return Err(ScriptError::StackOverflow);
+    }
+    stack.push(item);
+    Ok(())
+}
+
+/// Run `script` with `args` as the initial stack (the last argument on top)
+pub fn execute(script: &[u8], args: &[Vec<u8>], context: &ScriptContext) -> Result<(), ScriptError> {
+    if script.len() > MAX_SCRIPT_SIZE {
+        return Err(ScriptError::ScriptTooLarge);
+    }
+    let mut stack: Vec<Vec<u8>> = Vec::new();
+    for arg in args {
+        push(&mut stack, arg.clone())?;
+    }
+    let mut budget = MAX_SCRIPT_BUDGET;
+    let mut pc = 0;
+    while pc < script.len() {
+        let op = script[pc];
+        pc += 1;
+        let cost = match op {
+            OP_SHA256 => SHA256_COST,
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => CHECKSIG_COST,
+            _ => OP_COST,
+        };
+        budget = budget.checked_sub(cost).ok_or(ScriptError::BudgetExceeded)?;
+        match op {
+            OP_FALSE => push(&mut stack, Vec::new())?,
+            1..=OP_PUSH_MAX | OP_PUSHDATA1 | OP_PUSHDATA2 => {
+                let len = match op {
+                    OP_PUSHDATA1 => {
+                        let len = *script.get(pc).ok_or(ScriptError::Truncated)? as usize;
+                        pc += 1;
+                        len
+                    }
+                    OP_PUSHDATA2 => {
+                        let len = script.get(pc..pc + 2).ok_or(ScriptError::Truncated)?;
+                        pc += 2;
+                        u16::from_le_bytes([len[0], len[1]]) as usize
+                    }
+                    _ => op as usize,
+                };
+                let data = script.get(pc..pc + len).ok_or(ScriptError::Truncated)?;
+                push(&mut stack, data.to_vec())?;
+                pc += len;
+            }
+            OP_TRUE => push(&mut stack, vec![1])?,
+            OP_VERIFY => {
+                if !is_true(&pop(&mut stack)?) {
+                    return Err(ScriptError::VerifyFailed);
+                }
+            }
+            OP_DROP => {
+                pop(&mut stack)?;
+            }
+            OP_DUP => {
+                let top = stack.last().ok_or(ScriptError::StackUnderflow)?.clone();
+                push(&mut stack, top)?;
+            }
+            OP_SWAP => {
+                let a = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                stack.push(a);
+                stack.push(b);
+            }
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let a = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                if op == OP_EQUALVERIFY {
+                    if a != b {
+                        return Err(ScriptError::VerifyFailed);
+                    }
+                } else {
+                    stack.push(from_bool(a == b));
+                }
+            }
+            OP_NOT => {
+                let a = pop(&mut stack)?;
+                stack.push(from_bool(!is_true(&a)));
+            }
+            OP_BOOLAND | OP_BOOLOR => {
+                let a = is_true(&pop(&mut stack)?);
+                let b = is_true(&pop(&mut stack)?);
+                stack.push(from_bool(if op == OP_BOOLAND { a && b } else { a || b }));
+            }
+            OP_SHA256 => {
+                let a = pop(&mut stack)?;
+                stack.push(digest::digest(&digest::SHA256, &a).as_ref().to_vec());
+            }
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let public_key = pop(&mut stack)?;
+                let sig = pop(&mut stack)?;
+                let valid = signature::UnparsedPublicKey::new(&signature::ED25519, &public_key)
+                    .verify(context.message, &sig)
+                    .is_ok();
+                if op == OP_CHECKSIGVERIFY {
+                    if !valid {
+                        return Err(ScriptError::VerifyFailed);
+                    }
+                } else {
+                    stack.push(from_bool(valid));
+                }
+            }
+            OP_CHECKHEIGHT => {
+                let a = pop(&mut stack)?;
+                if a.len() > 8 {
+                    return Err(ScriptError::InvalidHeight);
+                }
+                let height = a.iter().fold(0u64, |height, byte| height << 8 | *byte as u64);
+                if context.height < height {
+                    return Err(ScriptError::HeightNotReached);
+                }
+            }
+            _ => return Err(ScriptError::InvalidOpcode(op)),
+        }
+    }
+    match stack.last() {
+        Some(top) if is_true(top) => Ok(()),
+        _ => Err(ScriptError::Failed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::key_pair;
+    use ring::signature::KeyPair;
+
+    const CONTEXT: ScriptContext = ScriptContext { message: b"transaction", height: 10 };
+
+    #[test]
+    fn hash_lock() {
+        let preimage = b"secret".to_vec();
+        let mut script = vec![OP_SHA256];
+        push_data(&mut script, digest::digest(&digest::SHA256, &preimage).as_ref());
+        script.push(OP_EQUAL);
+        assert_eq!(execute(&script, &[preimage], &CONTEXT), Ok(()));
+        assert_eq!(execute(&script, &[b"guess".to_vec()], &CONTEXT), Err(ScriptError::Failed));
+        assert_eq!(execute(&script, &[], &CONTEXT), Err(ScriptError::StackUnderflow));
+    }
+
+    #[test]
+    fn signature_and_height() {
+        let key = key_pair::random();
+        // <pubkey> CHECKSIGVERIFY <12> CHECKHEIGHT TRUE
+        let mut script: Vec<u8> = Vec::new();
+        push_data(&mut script, key.public_key().as_ref());
+        script.push(OP_CHECKSIGVERIFY);
+        push_data(&mut script, &[12]);
+        script.push(OP_CHECKHEIGHT);
+        script.push(OP_TRUE);
+        let sig = key.sign(CONTEXT.message).as_ref().to_vec();
+        assert_eq!(execute(&script, std::slice::from_ref(&sig), &CONTEXT), Err(ScriptError::HeightNotReached));
+        let later = ScriptContext { message: CONTEXT.message, height: 12 };
+        assert_eq!(execute(&script, &[sig], &later), Ok(()));
+        let other_sig = key.sign(b"another transaction").as_ref().to_vec();
+        assert_eq!(execute(&script, &[other_sig], &later), Err(ScriptError::VerifyFailed));
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(execute(&[0xff], &[], &CONTEXT), Err(ScriptError::InvalidOpcode(0xff)));
+        assert_eq!(execute(&[5, 1, 2], &[], &CONTEXT), Err(ScriptError::Truncated));
+        assert_eq!(execute(&[OP_TRUE; MAX_SCRIPT_SIZE + 1], &[], &CONTEXT), Err(ScriptError::ScriptTooLarge));
+        assert_eq!(execute(&[OP_TRUE; MAX_STACK_SIZE + 1], &[], &CONTEXT), Err(ScriptError::StackOverflow));
+        // 11 signature checks cost more than the budget even though each one is cheap to encode
+        let mut script: Vec<u8> = Vec::new();
+        for _ in 0..11 {
+            script.extend_from_slice(&[OP_FALSE, OP_FALSE, OP_CHECKSIG, OP_DROP]);
+        }
+        script.push(OP_TRUE);
+        assert_eq!(execute(&script, &[], &CONTEXT), Err(ScriptError::BudgetExceeded));
+        assert_eq!(execute(&[OP_TRUE, OP_FALSE, OP_BOOLOR, OP_TRUE, OP_BOOLAND], &[], &CONTEXT), Ok(()));
+        assert_eq!(execute(&[OP_TRUE, OP_NOT], &[], &CONTEXT), Err(ScriptError::Failed));
+    }
+
+    #[test]
+    fn push_sizes() {
+        for len in [1, 75, 76, 255, 256, 300] {
+            let data = vec![7u8; len];
+            let mut script: Vec<u8> = Vec::new();
+            push_data(&mut script, &data);
+            script.push(OP_SHA256);
+            push_data(&mut script, digest::digest(&digest::SHA256, &data).as_ref());
+            script.push(OP_EQUAL);
+            assert_eq!(execute(&script, &[], &CONTEXT), Ok(()), "push of {} bytes", len);
+        }
+        let mut script: Vec<u8> = Vec::new();
+        push_data(&mut script, &[7u8; 256]);
+        assert_eq!(&script[..3], &[OP_PUSHDATA2, 0, 1]);
+        assert_eq!(execute(&[OP_PUSHDATA2, 1], &[], &CONTEXT), Err(ScriptError::Truncated));
+    }
+}
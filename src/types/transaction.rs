@@ -1,8 +1,10 @@
 use serde::{Serialize,Deserialize};
-use ring::signature::{Ed25519KeyPair, Signature, KeyPair, VerificationAlgorithm, EdDSAParameters, self};
+use ring::signature::{Ed25519KeyPair, EcdsaKeyPair, Signature, KeyPair, VerificationAlgorithm, EdDSAParameters, self};
 use rand::Rng;
 
 use super::address::Address;
+use super::encoding::{Encode, ENCODING_VERSION, encode_bytes, encode_list, encode_option};
+use super::script::{self, ScriptContext};
 use crate::types::hash::{H256, Hashable};
 
 
@@ -13,23 +15,150 @@ pub struct Transaction {
     reciever: Address,
     value: u32,
     account_nonce: u32,
+    // batch payments: when non-empty these outputs replace `reciever`/`value`
+    outputs: Vec<(Address, u32)>,
+    // the chain this transaction is valid on; covered by the signature so it cannot be replayed elsewhere
+    chain_id: u32,
+    // application data such as an invoice id, at most MAX_MEMO_SIZE bytes
+    memo: Vec<u8>,
+    // not minable until the chain tip reaches this level (0 = no lock)
+    lock_height: u64,
+    // not minable until the median time of the last blocks reaches this timestamp in ms (0 = no lock)
+    lock_time: u128,
+    // hash time-locked contract step, if this transaction is one
+    htlc: Option<HtlcAction>,
 
 }
 
+/// Chain id used when none is configured
+pub const DEFAULT_CHAIN_ID: u32 = 0;
+
+/// Maximum length of a transaction memo, in bytes
+pub const MAX_MEMO_SIZE: usize = 256;
+
 #[derive(Serialize, Deserialize, Debug, Default, Clone)]
 pub struct SignedTransaction {
     transaction: Transaction,
     signature: Vec<u8>,
     public_key: Vec<u8>,
+    // set instead of signature/public_key when the sender is a multisig address
+    multisig: Option<MultisigWitness>,
+    // algorithm of signature/public_key
+    scheme: SignatureScheme,
+    // set instead of the fields above when the sender is a script address
+    script: Option<ScriptWitness>,
     
 }
 
+/// Signature algorithm of a single-key account. The address of an account depends on its scheme.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureScheme {
+    #[default]
+    Ed25519,
+    /// ECDSA on P-256 with SHA-256; fixed-length (r || s) signatures and uncompressed public keys
+    EcdsaP256,
+}
+
+/// A step of a hash time-locked contract (HTLC), used for atomic swaps.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum HtlcAction {
+    /// Lock `value` from the sender for `reciever`. The lock is identified by the hash of this signed transaction.
+    Lock { hashlock: H256, timeout: u64 },
+    /// Pay a lock to its receiver, who sends this transaction, by revealing a preimage of the hashlock
+    /// in a block below the timeout height
+    Claim { lock_id: H256, preimage: Vec<u8> },
+    /// Return a lock to its sender, who sends this transaction, in a block at or above the timeout height
+    Refund { lock_id: H256 },
+}
+
+/// Maximum number of keys in a multisig account
+pub const MAX_MULTISIG_KEYS: usize = 16;
+
+/// Keys and signatures authorizing a spend from an M-of-N multisig address.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct MultisigWitness {
+    threshold: u8,
+    public_keys: Vec<Vec<u8>>,
+    // (index into public_keys, signature of the transaction)
+    signatures: Vec<(u8, Vec<u8>)>,
+}
+
+/// Script and arguments authorizing a spend from a script address (see `types::script`).
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct ScriptWitness {
+    script: Vec<u8>,
+    // pushed on the stack before the script runs, last one on top
+    args: Vec<Vec<u8>>,
+}
+
+impl ScriptWitness {
+    pub fn new(script: Vec<u8>, args: Vec<Vec<u8>>) -> Self {
+        Self { script, args }
+    }
+    pub fn get_script(&self) -> Vec<u8> {
+        self.script.clone()
+    }
+    pub fn get_args(&self) -> Vec<Vec<u8>> {
+        self.args.clone()
+    }
+    pub fn address(&self) -> Address {
+        Address::from_script(&self.script)
+    }
+}
+
+impl MultisigWitness {
+    pub fn new(threshold: u8, public_keys: Vec<Vec<u8>>, signatures: Vec<(u8, Vec<u8>)>) -> Self {
+        Self { threshold, public_keys, signatures }
+    }
+    pub fn get_threshold(&self) -> u8 {
+        self.threshold
+    }
+    pub fn get_public_keys(&self) -> Vec<Vec<u8>> {
+        self.public_keys.clone()
+    }
+    pub fn get_signatures(&self) -> Vec<(u8, Vec<u8>)> {
+        self.signatures.clone()
+    }
+    /// `None` if the keys and threshold do not make a valid multisig account
+    pub fn address(&self) -> Option<Address> {
+        Address::from_multisig(self.threshold, &self.public_keys)
+    }
+}
+
 impl Transaction {
     pub fn new(sender: Address,
         reciever: Address,
         value: u32,
-        account_nonce: u32,) -> Self {
-        Self { sender: sender, reciever: reciever, value: value, account_nonce: account_nonce }
+        account_nonce: u32,
+        chain_id: u32,) -> Self {
+        Self { sender, reciever, value, account_nonce, outputs: Vec::new(), chain_id, memo: Vec::new(), lock_height: 0, lock_time: 0, htlc: None }
+    }
+    /// Create a batch payment that pays every `(Address, amount)` in `outputs` under one signature.
+    pub fn new_batch(sender: Address,
+        outputs: Vec<(Address, u32)>,
+        account_nonce: u32,
+        chain_id: u32,) -> Self {
+        Self { sender, reciever: Address::default(), value: 0, account_nonce, outputs, chain_id, memo: Vec::new(), lock_height: 0, lock_time: 0, htlc: None }
+    }
+    /// Attach a memo to the transaction; it must be set before signing
+    pub fn with_memo(mut self, memo: Vec<u8>) -> Self {
+        self.memo = memo;
+        self
+    }
+    /// Time-lock the transaction until the chain tip reaches `lock_height`; set before signing
+    pub fn with_lock_height(mut self, lock_height: u64) -> Self {
+        self.lock_height = lock_height;
+        self
+    }
+    /// Time-lock the transaction until the chain's median time reaches `lock_time` (ms since UNIX epoch); set before signing
+    pub fn with_lock_time(mut self, lock_time: u128) -> Self {
+        self.lock_time = lock_time;
+        self
+    }
+    /// Make the transaction an HTLC step; set before signing. Claims and refunds carry no value of their own.
+    pub fn with_htlc(mut self, htlc: HtlcAction) -> Self {
+        self.htlc = Some(htlc);
+        self
     }
     pub fn get_sender(&self) -> Address {
         self.sender
@@ -44,6 +173,36 @@ impl Transaction {
     pub fn get_account_nonce(&self) -> u32 {
         self.account_nonce
     }
+    pub fn get_chain_id(&self) -> u32 {
+        self.chain_id
+    }
+    pub fn get_memo(&self) -> Vec<u8> {
+        self.memo.clone()
+    }
+    pub fn get_lock_height(&self) -> u64 {
+        self.lock_height
+    }
+    pub fn get_lock_time(&self) -> u128 {
+        self.lock_time
+    }
+    pub fn get_htlc(&self) -> Option<HtlcAction> {
+        self.htlc.clone()
+    }
+    pub fn is_batch(&self) -> bool {
+        !self.outputs.is_empty()
+    }
+    /// All (reciever, value) pairs paid by this transaction
+    pub fn get_outputs(&self) -> Vec<(Address, u32)> {
+        if self.is_batch() {
+            self.outputs.clone()
+        } else {
+            vec![(self.reciever, self.value)]
+        }
+    }
+    /// Sum of all output values, or None if it overflows a u32
+    pub fn get_total_value(&self) -> Option<u32> {
+        self.get_outputs().iter().try_fold(0u32, |total, (_, value)| total.checked_add(*value))
+    }
 }
 
 impl SignedTransaction {
@@ -52,7 +211,21 @@ impl SignedTransaction {
         signature: Vec<u8>,
         public_key: Vec<u8>,
     ) -> Self {
-        Self { transaction: transaction, signature: signature, public_key: public_key }
+        Self { transaction, signature, public_key, multisig: None, scheme: SignatureScheme::Ed25519, script: None }
+    }
+    pub fn new_with_scheme(
+        transaction: Transaction,
+        signature: Vec<u8>,
+        public_key: Vec<u8>,
+        scheme: SignatureScheme,
+    ) -> Self {
+        Self { transaction, signature, public_key, multisig: None, scheme, script: None }
+    }
+    pub fn new_multisig(transaction: Transaction, witness: MultisigWitness) -> Self {
+        Self { transaction, signature: Vec::new(), public_key: Vec::new(), multisig: Some(witness), scheme: SignatureScheme::Ed25519, script: None }
+    }
+    pub fn new_script(transaction: Transaction, witness: ScriptWitness) -> Self {
+        Self { transaction, signature: Vec::new(), public_key: Vec::new(), multisig: None, scheme: SignatureScheme::Ed25519, script: Some(witness) }
     }
     pub fn get_transaction(&self) -> Transaction {
         self.transaction.clone()
@@ -63,13 +236,105 @@ impl SignedTransaction {
     pub fn get_public_key(&self) -> Vec<u8> {
         self.public_key.clone()
     }
+    pub fn get_multisig(&self) -> Option<MultisigWitness> {
+        self.multisig.clone()
+    }
+    pub fn get_scheme(&self) -> SignatureScheme {
+        self.scheme
+    }
+    pub fn get_script(&self) -> Option<ScriptWitness> {
+        self.script.clone()
+    }
 }
 
 
+impl Encode for Transaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        ENCODING_VERSION.encode(out);
+        self.sender.encode(out);
+        self.reciever.encode(out);
+        self.value.encode(out);
+        self.account_nonce.encode(out);
+        encode_list(&self.outputs, out);
+        self.chain_id.encode(out);
+        encode_bytes(&self.memo, out);
+        self.lock_height.encode(out);
+        self.lock_time.encode(out);
+        encode_option(&self.htlc, out);
+    }
+}
+
+impl Encode for HtlcAction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            HtlcAction::Lock { hashlock, timeout } => {
+                0u8.encode(out);
+                hashlock.encode(out);
+                timeout.encode(out);
+            }
+            HtlcAction::Claim { lock_id, preimage } => {
+                1u8.encode(out);
+                lock_id.encode(out);
+                encode_bytes(preimage, out);
+            }
+            HtlcAction::Refund { lock_id } => {
+                2u8.encode(out);
+                lock_id.encode(out);
+            }
+        }
+    }
+}
+
+impl Encode for SignatureScheme {
+    fn encode(&self, out: &mut Vec<u8>) {
+        let tag: u8 = match self {
+            SignatureScheme::Ed25519 => 0,
+            SignatureScheme::EcdsaP256 => 1,
+        };
+        tag.encode(out);
+    }
+}
+
+impl Encode for MultisigWitness {
+    fn encode(&self, out: &mut Vec<u8>) {
+        self.threshold.encode(out);
+        (self.public_keys.len() as u32).encode(out);
+        for public_key in self.public_keys.iter() {
+            encode_bytes(public_key, out);
+        }
+        (self.signatures.len() as u32).encode(out);
+        for (index, signature) in self.signatures.iter() {
+            index.encode(out);
+            encode_bytes(signature, out);
+        }
+    }
+}
+
+impl Encode for ScriptWitness {
+    fn encode(&self, out: &mut Vec<u8>) {
+        encode_bytes(&self.script, out);
+        (self.args.len() as u32).encode(out);
+        for arg in self.args.iter() {
+            encode_bytes(arg, out);
+        }
+    }
+}
+
+impl Encode for SignedTransaction {
+    fn encode(&self, out: &mut Vec<u8>) {
+        ENCODING_VERSION.encode(out);
+        self.transaction.encode(out);
+        encode_bytes(&self.signature, out);
+        encode_bytes(&self.public_key, out);
+        encode_option(&self.multisig, out);
+        self.scheme.encode(out);
+        encode_option(&self.script, out);
+    }
+}
+
 impl Hashable for SignedTransaction{
     fn hash(&self) -> H256 {
-        let serial_signed = serde_json::to_string(self);
-        ring::digest::digest(&ring::digest::SHA256, serial_signed.unwrap().as_bytes()).into()
+        ring::digest::digest(&ring::digest::SHA256, &self.encoded()).into()
     }
 }
 
@@ -77,9 +342,7 @@ impl Hashable for SignedTransaction{
 /// Create digital signature of a transaction
 /// Do i need to edit for bigger transactions? -> SHA256 can handle any length automatically
 pub fn sign(t: &Transaction, key: &Ed25519KeyPair) -> Signature {
-    let serial_transaction = serde_json::to_string(t);
-    let sig = key.sign(serial_transaction.unwrap().as_bytes());
-    sig
+    key.sign(&t.encoded())
 }
 
 pub fn sig_to_vec(signature: Signature) -> Vec<u8>{
@@ -89,12 +352,84 @@ pub fn sig_to_vec(signature: Signature) -> Vec<u8>{
 
 /// Verify digital signature of a transaction, using public key instead of secret key
 pub fn verify(t: &Transaction, public_key: &[u8], signature: &[u8]) -> bool {
-    let serial_transaction = serde_json::to_string(t);
     let good_public_key = ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, public_key);
-    good_public_key.verify(serial_transaction.unwrap().as_bytes(),signature).is_ok()
+    good_public_key.verify(&t.encoded(),signature).is_ok()
  
     
 }
+
+/// Create an ECDSA P-256 signature of a transaction
+pub fn sign_p256(t: &Transaction, key: &EcdsaKeyPair) -> Vec<u8> {
+    let rng = ring::rand::SystemRandom::new();
+    key.sign(&rng, &t.encoded()).unwrap().as_ref().to_vec()
+}
+
+/// Verify a signature of a transaction made with the given scheme
+pub fn verify_with_scheme(t: &Transaction, scheme: SignatureScheme, public_key: &[u8], signature: &[u8]) -> bool {
+    match scheme {
+        SignatureScheme::Ed25519 => verify(t, public_key, signature),
+        SignatureScheme::EcdsaP256 => {
+            let good_public_key = signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, public_key);
+            good_public_key.verify(&t.encoded(), signature).is_ok()
+        }
+    }
+}
+
+/// Verify that a signed transaction was signed for `chain_id` and is authorized to spend from its
+/// sender address: either one signature by the key hashing to the sender, or at least `threshold`
+/// valid signatures by distinct keys of the multisig account hashing to the sender, or a script hashing
+/// to the sender that succeeds for a transaction included at `height`.
+pub fn verify_signed(st: &SignedTransaction, chain_id: u32, height: u64) -> bool {
+    let transaction = &st.transaction;
+    if transaction.chain_id != chain_id || transaction.memo.len() > MAX_MEMO_SIZE {
+        return false;
+    }
+    if let Some(witness) = &st.script {
+        let message = transaction.encoded();
+        let context = ScriptContext { message: &message, height };
+        return witness.address() == transaction.get_sender() && script::execute(&witness.script, &witness.args, &context).is_ok();
+    }
+    match &st.multisig {
+        None => {
+            Address::from_scheme_public_key(st.scheme, &st.public_key) == transaction.get_sender()
+                && verify_with_scheme(transaction, st.scheme, &st.public_key, &st.signature)
+        }
+        Some(witness) => {
+            // also rejects repeated keys, so one signer cannot fill several slots
+            if witness.address() != Some(transaction.get_sender()) {
+                return false;
+            }
+            let mut signed_by: Vec<u8> = Vec::new();
+            for (index, signature) in witness.signatures.iter() {
+                if signed_by.contains(index) {
+                    continue;
+                }
+                if let Some(public_key) = witness.public_keys.get(*index as usize) {
+                    if verify(transaction, public_key, signature) {
+                        signed_by.push(*index);
+                    }
+                }
+            }
+            signed_by.len() >= witness.threshold as usize
+        }
+    }
+}
+/// Run `verify_signed` over many transactions split across up to `threads` threads, returning
+/// the result for each transaction in order
+pub fn verify_signed_batch(sts: &[SignedTransaction], chain_id: u32, height: u64, threads: usize) -> Vec<bool> {
+    if sts.is_empty() {
+        return Vec::new();
+    }
+    let chunk_size = sts.len().div_ceil(threads.max(1));
+    std::thread::scope(|s| {
+        let handles: Vec<_> = sts
+            .chunks(chunk_size)
+            .map(|chunk| s.spawn(move || chunk.iter().map(|st| verify_signed(st, chain_id, height)).collect::<Vec<bool>>()))
+            .collect();
+        handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+    })
+}
+
 pub fn generate_random_transaction_1() -> (SignedTransaction) {
     fn generate_random_address() -> (Address, Vec<u8>, Ed25519KeyPair){
         let rng = ring::rand::SystemRandom::new();
@@ -109,10 +444,10 @@ pub fn generate_random_transaction_1() -> (SignedTransaction) {
     let (address2, pub2, keys2) = generate_random_address();
     let mut rng = rand::thread_rng();
     let val: u32 = rng.gen();
-    let rand_transact = Transaction {sender: address1, reciever: address2, value: val, account_nonce: 0};
+    let rand_transact = Transaction::new(address1, address2, val, 0, DEFAULT_CHAIN_ID);
     let tx_c = rand_transact.clone();
     let signat = sign(&rand_transact,&keys1);
-    let signed_tx = SignedTransaction { transaction: tx_c, signature:sig_to_vec(signat), public_key: pub1 };
+    let signed_tx = SignedTransaction::new(tx_c, sig_to_vec(signat), pub1);
     signed_tx
     
 }
@@ -132,7 +467,7 @@ pub fn generate_random_transaction() -> Transaction {
     let address2 = generate_random_address();
     let mut rng = rand::thread_rng();
     let val: u32 = rng.gen();
-    let rand_transact = Transaction {sender: address1, reciever: address2, value: val, account_nonce: 0};
+    let rand_transact = Transaction::new(address1, address2, val, 0, DEFAULT_CHAIN_ID);
     rand_transact
     
 }
@@ -163,6 +498,166 @@ mod tests {
         assert!(!verify(&t_2, key.public_key().as_ref(), signature.as_ref()));
         assert!(!verify(&t, key_2.public_key().as_ref(), signature.as_ref()));
     }
+    #[test]
+    fn batch_outputs() {
+        let t = generate_random_transaction();
+        let outputs = vec![(t.get_reciever(), 5), (t.get_sender(), 7)];
+        let batch = Transaction::new_batch(t.get_sender(), outputs.clone(), 1, DEFAULT_CHAIN_ID);
+        assert_eq!(batch.get_outputs(), outputs);
+        assert_eq!(batch.get_total_value(), Some(12));
+        let key = key_pair::random();
+        let signature = sign(&batch, &key);
+        assert!(verify(&batch, key.public_key().as_ref(), signature.as_ref()));
+        let overflow = Transaction::new_batch(t.get_sender(), vec![(t.get_reciever(), u32::MAX), (t.get_reciever(), 1)], 1, DEFAULT_CHAIN_ID);
+        assert_eq!(overflow.get_total_value(), None);
+    }
+    #[test]
+    fn multisig_threshold() {
+        let keys: Vec<Ed25519KeyPair> = (0..3).map(|_| key_pair::random()).collect();
+        let public_keys: Vec<Vec<u8>> = keys.iter().map(|k| k.public_key().as_ref().to_vec()).collect();
+        let multisig_address = Address::from_multisig(2, &public_keys).unwrap();
+        let t = generate_random_transaction();
+        let t = Transaction::new(multisig_address, t.get_reciever(), 10, 1, DEFAULT_CHAIN_ID);
+        let sig = |i: usize| (i as u8, sig_to_vec(sign(&t, &keys[i])));
+
+        let two = MultisigWitness::new(2, public_keys.clone(), vec![sig(0), sig(2)]);
+        assert!(verify_signed(&SignedTransaction::new_multisig(t.clone(), two), DEFAULT_CHAIN_ID, 0));
+        // the same signature counted twice does not reach the threshold
+        let repeated = MultisigWitness::new(2, public_keys.clone(), vec![sig(1), sig(1)]);
+        assert!(!verify_signed(&SignedTransaction::new_multisig(t.clone(), repeated), DEFAULT_CHAIN_ID, 0));
+        // a different threshold derives a different address
+        let wrong_threshold = MultisigWitness::new(1, public_keys.clone(), vec![sig(0), sig(2)]);
+        assert!(!verify_signed(&SignedTransaction::new_multisig(t.clone(), wrong_threshold), DEFAULT_CHAIN_ID, 0));
+        // a signature by the wrong key does not count
+        let mislabeled = MultisigWitness::new(2, public_keys.clone(), vec![sig(0), (1, sig(2).1)]);
+        assert!(!verify_signed(&SignedTransaction::new_multisig(t.clone(), mislabeled), DEFAULT_CHAIN_ID, 0));
+
+        // one key listed twice would let its holder sign alone
+        let doubled = vec![public_keys[0].clone(), public_keys[0].clone()];
+        assert_eq!(Address::from_multisig(2, &doubled), None);
+        let t = Transaction::new(Address::from_public_key_bytes(b"doubled"), t.get_reciever(), 10, 1, DEFAULT_CHAIN_ID);
+        let doubled_witness = MultisigWitness::new(2, doubled, vec![(0, sig_to_vec(sign(&t, &keys[0]))), (1, sig_to_vec(sign(&t, &keys[0])))]);
+        assert!(!verify_signed(&SignedTransaction::new_multisig(t, doubled_witness), DEFAULT_CHAIN_ID, 0));
+        assert_eq!(Address::from_multisig(1, &[vec![1; 31]]), None);
+        let too_many: Vec<Vec<u8>> = (0..=MAX_MULTISIG_KEYS).map(|i| vec![i as u8; 32]).collect();
+        assert_eq!(Address::from_multisig(1, &too_many), None);
+        assert!(Address::from_multisig(1, &too_many[..MAX_MULTISIG_KEYS]).is_some());
+    }
+    #[test]
+    fn chain_id_replay() {
+        let key = key_pair::random();
+        let sender = Address::from_public_key_bytes(key.public_key().as_ref());
+        let t = generate_random_transaction();
+        let t = Transaction::new(sender, t.get_reciever(), 10, 1, 7);
+        let st = SignedTransaction::new(t.clone(), sig_to_vec(sign(&t, &key)), key.public_key().as_ref().to_vec());
+        assert!(verify_signed(&st, 7, 0));
+        assert!(!verify_signed(&st, 8, 0));
+        // changing the chain id invalidates the signature
+        let replayed = Transaction::new(sender, t.get_reciever(), 10, 1, 8);
+        assert!(!verify(&replayed, key.public_key().as_ref(), &st.get_signature()));
+    }
+    #[test]
+    fn memo_signed() {
+        let key = key_pair::random();
+        let sender = Address::from_public_key_bytes(key.public_key().as_ref());
+        let t = generate_random_transaction();
+        let t = Transaction::new(sender, t.get_reciever(), 10, 1, DEFAULT_CHAIN_ID).with_memo(b"invoice-42".to_vec());
+        let st = SignedTransaction::new(t.clone(), sig_to_vec(sign(&t, &key)), key.public_key().as_ref().to_vec());
+        assert!(verify_signed(&st, DEFAULT_CHAIN_ID, 0));
+        let altered = t.clone().with_memo(b"invoice-43".to_vec());
+        assert!(!verify(&altered, key.public_key().as_ref(), &st.get_signature()));
+        let altered = SignedTransaction::new(altered, st.get_signature(), st.get_public_key());
+        assert_ne!(altered.hash(), st.hash());
+        // memos over the bound are rejected even when correctly signed
+        let t = t.with_memo(vec![0; MAX_MEMO_SIZE + 1]);
+        let st = SignedTransaction::new(t.clone(), sig_to_vec(sign(&t, &key)), key.public_key().as_ref().to_vec());
+        assert!(!verify_signed(&st, DEFAULT_CHAIN_ID, 0));
+    }
+    #[test]
+    fn p256_scheme() {
+        let key = key_pair::random_p256();
+        let public_key = key.public_key().as_ref().to_vec();
+        let sender = Address::from_scheme_public_key(SignatureScheme::EcdsaP256, &public_key);
+        let t = generate_random_transaction();
+        let t = Transaction::new(sender, t.get_reciever(), 10, 1, DEFAULT_CHAIN_ID);
+        let signature = sign_p256(&t, &key);
+        let st = SignedTransaction::new_with_scheme(t.clone(), signature.clone(), public_key.clone(), SignatureScheme::EcdsaP256);
+        assert!(verify_signed(&st, DEFAULT_CHAIN_ID, 0));
+        // the same key and signature do not verify under another scheme
+        let wrong_scheme = SignedTransaction::new(t, signature, public_key.clone());
+        assert!(!verify_signed(&wrong_scheme, DEFAULT_CHAIN_ID, 0));
+        assert_ne!(Address::from_public_key_bytes(&public_key), sender);
+    }
+    #[test]
+    fn batch_verification() {
+        let mut sts: Vec<SignedTransaction> = (0..10).map(|_| generate_random_transaction_1()).collect();
+        sts[7] = SignedTransaction::new(generate_random_transaction(), sts[7].get_signature(), sts[7].get_public_key());
+        let results = verify_signed_batch(&sts, DEFAULT_CHAIN_ID, 0, 3);
+        let expected: Vec<bool> = (0..10).map(|i| i != 7).collect();
+        assert_eq!(results, expected);
+        assert!(verify_signed_batch(&[], DEFAULT_CHAIN_ID, 0, 3).is_empty());
+    }
+    #[test]
+    fn script_spend() {
+        let key = key_pair::random();
+        // spendable by `key` from height 5
+        let mut lock: Vec<u8> = Vec::new();
+        script::push_data(&mut lock, key.public_key().as_ref());
+        lock.push(script::OP_CHECKSIGVERIFY);
+        script::push_data(&mut lock, &[5]);
+        lock.push(script::OP_CHECKHEIGHT);
+        lock.push(script::OP_TRUE);
+        let sender = Address::from_script(&lock);
+        let t = Transaction::new(sender, generate_random_transaction().get_reciever(), 10, 1, DEFAULT_CHAIN_ID);
+        let args = vec![sig_to_vec(sign(&t, &key))];
+        let st = SignedTransaction::new_script(t.clone(), ScriptWitness::new(lock.clone(), args.clone()));
+        assert!(!verify_signed(&st, DEFAULT_CHAIN_ID, 4));
+        assert!(verify_signed(&st, DEFAULT_CHAIN_ID, 5));
+        // the script must hash to the sender
+        let mut other = lock.clone();
+        other.push(script::OP_TRUE);
+        assert!(!verify_signed(&SignedTransaction::new_script(t.clone(), ScriptWitness::new(other, args)), DEFAULT_CHAIN_ID, 5));
+        // the signature covers the transaction
+        let altered = Transaction::new(sender, t.get_reciever(), 11, 1, DEFAULT_CHAIN_ID);
+        assert!(!verify_signed(&SignedTransaction::new_script(altered, st.get_script().unwrap()), DEFAULT_CHAIN_ID, 5));
+    }
+    #[test]
+    fn htlc_signed() {
+        let key = key_pair::random();
+        let sender = Address::from_public_key_bytes(key.public_key().as_ref());
+        let claim = HtlcAction::Claim { lock_id: H256::from([3; 32]), preimage: b"secret".to_vec() };
+        let t = Transaction::new(sender, sender, 0, 1, DEFAULT_CHAIN_ID).with_htlc(claim);
+        let st = SignedTransaction::new(t.clone(), sig_to_vec(sign(&t, &key)), key.public_key().as_ref().to_vec());
+        assert!(verify_signed(&st, DEFAULT_CHAIN_ID, 0));
+        // a relayer cannot swap in another preimage or lock
+        let altered = t.with_htlc(HtlcAction::Refund { lock_id: H256::from([3; 32]) });
+        assert!(!verify(&altered, key.public_key().as_ref(), &st.get_signature()));
+    }
+    #[test]
+    fn canonical_encoding() {
+        // fixed vector for reimplementations of the encoding
+        let t = Transaction::new(Address::from([1; 20]), Address::from([2; 20]), 10, 3, 7).with_memo(b"hi".to_vec());
+        let expected = [
+            "01",
+            &"01".repeat(20),
+            &"02".repeat(20),
+            "0000000a",
+            "00000003",
+            "00000000",
+            "00000007",
+            "000000026869",
+            "0000000000000000",
+            &"00".repeat(16),
+            "00",
+        ].concat();
+        assert_eq!(hex::encode(t.encoded()), expected);
+
+        let st = SignedTransaction::new(t, vec![0xaa; 2], vec![0xbb; 3]);
+        let expected_signed = ["01", &expected, "00000002aaaa", "00000003bbbbbb", "00", "00", "00"].concat();
+        assert_eq!(hex::encode(st.encoded()), expected_signed);
+        let digest = ring::digest::digest(&ring::digest::SHA256, &hex::decode(&expected_signed).unwrap());
+        assert_eq!(st.hash(), H256::from(digest));
+    }
 }
 
 // DO NOT CHANGE THIS COMMENT, IT IS FOR AUTOGRADER. AFTER TEST
\ No newline at end of file
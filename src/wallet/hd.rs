@@ -0,0 +1,197 @@
+use ring::hmac;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+use std::collections::HashMap;
+
+use crate::types::address::Address;
+
+/// Number of consecutive unused child addresses after which `scan` stops
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Deterministic Ed25519 key tree (SLIP-0010, hardened children only) derived from one master seed.
+pub struct HdWallet {
+    key: [u8; 32],
+    chain_code: [u8; 32],
+}
+
+/// Split HMAC-SHA512(key, data) into its left and right halves
+fn hmac_sha512(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let tag = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA512, key), data);
+    let mut left = [0u8; 32];
+    let mut right = [0u8; 32];
+    left.copy_from_slice(&tag.as_ref()[..32]);
+    right.copy_from_slice(&tag.as_ref()[32..]);
+    (left, right)
+}
+
+impl HdWallet {
+    pub fn from_seed(seed: &[u8]) -> Self {
+        let (key, chain_code) = hmac_sha512(b"ed25519 seed", seed);
+        HdWallet { key, chain_code }
+    }
+
+    /// Seed of the hardened child key number `index`
+    pub fn child_seed(&self, index: u32) -> [u8; 32] {
+        let mut data: Vec<u8> = vec![0];
+        data.extend_from_slice(&self.key);
+        data.extend_from_slice(&(index | 0x8000_0000).to_be_bytes());
+        let (child_key, _) = hmac_sha512(&self.chain_code, &data);
+        child_key
+    }
+
+    pub fn key_pair(&self, index: u32) -> Ed25519KeyPair {
+        Ed25519KeyPair::from_seed_unchecked(&self.child_seed(index)).unwrap()
+    }
+
+    pub fn address(&self, index: u32) -> Address {
+        Address::from_public_key_bytes(self.key_pair(index).public_key().as_ref())
+    }
+
+    /// Child addresses that appear in `state`, stopping after `gap_limit` consecutive unused indices
+    pub fn scan(&self, state: &HashMap<Address, (u32, u32)>, gap_limit: u32) -> Vec<(u32, Address)> {
+        let mut used: Vec<(u32, Address)> = Vec::new();
+        let mut gap = 0;
+        let mut index = 0;
+        while gap < gap_limit {
+            let address = self.address(index);
+            if state.contains_key(&address) {
+                used.push((index, address));
+                gap = 0;
+            } else {
+                gap += 1;
+            }
+            index += 1;
+        }
+        used
+    }
+}
+
+/// A fresh master seed from the system random number generator
+pub fn random_seed() -> [u8; 32] {
+    let mut seed = [0u8; 32];
+    SystemRandom::new().fill(&mut seed).unwrap();
+    seed
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MnemonicError {
+    UnknownWord(String),
+    WrongLength(usize),
+    InvalidChecksum,
+}
+
+impl std::fmt::Display for MnemonicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MnemonicError::UnknownWord(w) => write!(f, "unknown mnemonic word '{}'", w),
+            MnemonicError::WrongLength(n) => write!(f, "expected 33 mnemonic words, got {}", n),
+            MnemonicError::InvalidChecksum => write!(f, "mnemonic checksum mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for MnemonicError {}
+
+/// Backup format for a 32-byte seed: one word per byte, followed by a checksum word for the
+/// first byte of SHA256(seed)
+pub fn to_mnemonic(seed: &[u8; 32]) -> String {
+    let check = digest::digest(&digest::SHA256, seed).as_ref()[0];
+    let words: Vec<&str> = seed.iter().chain(std::iter::once(&check)).map(|b| WORDS[*b as usize]).collect();
+    words.join(" ")
+}
+
+/// Restore a seed written with `to_mnemonic`
+pub fn from_mnemonic(mnemonic: &str) -> Result<[u8; 32], MnemonicError> {
+    let mut bytes: Vec<u8> = Vec::new();
+    for word in mnemonic.split_whitespace() {
+        let word = word.to_lowercase();
+        match WORDS.iter().position(|w| *w == word) {
+            Some(b) => bytes.push(b as u8),
+            None => return Err(MnemonicError::UnknownWord(word)),
+        }
+    }
+    if bytes.len() != 33 {
+        return Err(MnemonicError::WrongLength(bytes.len()));
+    }
+    let mut seed = [0u8; 32];
+    seed.copy_from_slice(&bytes[..32]);
+    if digest::digest(&digest::SHA256, &seed).as_ref()[0] != bytes[32] {
+        return Err(MnemonicError::InvalidChecksum);
+    }
+    Ok(seed)
+}
+
+const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adult", "agent", "alarm", "album", "alert",
+    "alley", "amber", "angle", "ankle", "apple", "april", "arena", "armor",
+    "arrow", "atlas", "attic", "audio", "autumn", "avenue", "badge", "bagel",
+    "baker", "bamboo", "banjo", "barn", "basil", "basket", "beach", "beard",
+    "beaver", "bench", "berry", "bison", "blade", "boat", "bonus", "border",
+    "bottle", "brain", "branch", "bread", "brick", "bridge", "broom", "bubble",
+    "bucket", "bundle", "butter", "cabin", "cactus", "camel", "canal", "candle",
+    "canoe", "canyon", "carbon", "carpet", "carrot", "castle", "cattle", "cedar",
+    "cellar", "cement", "chalk", "cherry", "chess", "chief", "circle", "citrus",
+    "clay", "cliff", "clock", "cloud", "clover", "coast", "cobalt", "coffee",
+    "comet", "copper", "coral", "cotton", "cousin", "coyote", "crane", "crater",
+    "cream", "crystal", "cube", "cycle", "daisy", "dancer", "delta", "desert",
+    "diamond", "dinner", "doctor", "dolphin", "donkey", "dragon", "drum", "eagle",
+    "earth", "echo", "eclipse", "elbow", "ember", "engine", "falcon", "fabric",
+    "feather", "fence", "fiber", "fiddle", "filter", "flame", "flute", "forest",
+    "fossil", "fox", "galaxy", "garden", "garlic", "gate", "giant", "ginger",
+    "glacier", "globe", "glove", "goat", "gold", "grape", "gravel", "guitar",
+    "hammer", "harbor", "harvest", "hawk", "hazel", "helmet", "hero", "hill",
+    "honey", "horizon", "hotel", "island", "ivory", "jacket", "jaguar", "jelly",
+    "jewel", "jungle", "kettle", "kitten", "koala", "ladder", "lagoon", "lantern",
+    "laptop", "lemon", "leopard", "lily", "lion", "lizard", "lobster", "lotus",
+    "lunar", "magnet", "mango", "maple", "marble", "meadow", "melon", "mirror",
+    "monkey", "moon", "mosaic", "motor", "napkin", "nectar", "needle", "nest",
+    "noodle", "oasis", "ocean", "olive", "onion", "orange", "orbit", "orchid",
+    "otter", "oven", "owl", "oyster", "paddle", "palace", "panda", "paper",
+    "parrot", "peach", "peanut", "pearl", "pebble", "pencil", "pepper", "piano",
+    "pilot", "pine", "planet", "plaza", "pocket", "pony", "potato", "prairie",
+    "pumpkin", "puzzle", "quartz", "rabbit", "radar", "raven", "river", "robot",
+    "rocket", "saddle", "salmon", "sand", "scarf", "shadow", "shell", "silver",
+    "sparrow", "spider", "spoon", "stone", "summit", "sunset", "swan", "tiger",
+    "tomato", "tunnel", "turtle", "valley", "velvet", "violin", "volcano", "walnut",
+    "whale", "willow", "window", "winter", "wizard", "wolf", "yogurt", "zebra",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slip10_vector() {
+        // SLIP-0010 ed25519 test vector 1, chain m/0H
+        let wallet = HdWallet::from_seed(&hex!("000102030405060708090a0b0c0d0e0f"));
+        assert_eq!(wallet.key, hex!("2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"));
+        assert_eq!(wallet.chain_code, hex!("90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb"));
+        assert_eq!(wallet.child_seed(0), hex!("68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"));
+    }
+
+    #[test]
+    fn mnemonic_round_trip() {
+        let seed = random_seed();
+        let mnemonic = to_mnemonic(&seed);
+        assert_eq!(from_mnemonic(&mnemonic), Ok(seed));
+        // replace the checksum word with any other word
+        let mut words: Vec<&str> = mnemonic.split(' ').collect();
+        let check = WORDS.iter().position(|w| *w == words[32]).unwrap();
+        words[32] = WORDS[(check + 1) % WORDS.len()];
+        assert_eq!(from_mnemonic(&words.join(" ")), Err(MnemonicError::InvalidChecksum));
+        assert_eq!(from_mnemonic("acid acorn"), Err(MnemonicError::WrongLength(2)));
+    }
+
+    #[test]
+    fn scan_used_children() {
+        let wallet = HdWallet::from_seed(&random_seed());
+        let mut state: HashMap<Address, (u32, u32)> = HashMap::new();
+        state.insert(wallet.address(0), (0, 5));
+        state.insert(wallet.address(3), (1, 0));
+        assert_eq!(wallet.scan(&state, 5), vec![(0, wallet.address(0)), (3, wallet.address(3))]);
+        // index 3 lies beyond a gap of 2
+        assert_eq!(wallet.scan(&state, 2), vec![(0, wallet.address(0))]);
+    }
+}
@@ -0,0 +1,183 @@
+pub mod hd;
+
+use serde::{Serialize, Deserialize};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use log::info;
+
+use std::fs;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+
+use crate::types::address::Address;
+
+/// PBKDF2-HMAC-SHA256 iterations used for newly written key files
+pub const PBKDF2_ITERATIONS: u32 = 100_000;
+const SALT_LEN: usize = 16;
+
+/// A key file in the keystore directory: a PKCS#8 Ed25519 key encrypted with AES-256-GCM under a
+/// key derived from the passphrase with PBKDF2. The address is authenticated as associated data.
+#[derive(Serialize, Deserialize)]
+struct KeyFile {
+    address: String,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug)]
+pub enum WalletError {
+    Io(std::io::Error),
+    Format(String),
+    /// wrong passphrase or a corrupted key file
+    Decrypt(PathBuf),
+}
+
+impl std::fmt::Display for WalletError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WalletError::Io(e) => write!(f, "keystore io error: {}", e),
+            WalletError::Format(e) => write!(f, "malformed key file: {}", e),
+            WalletError::Decrypt(path) => write!(f, "cannot decrypt {}: wrong passphrase or corrupted file", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+impl From<std::io::Error> for WalletError {
+    fn from(e: std::io::Error) -> Self {
+        WalletError::Io(e)
+    }
+}
+
+/// Ed25519 keys loaded from an encrypted on-disk keystore.
+pub struct Wallet {
+    dir: PathBuf,
+    passphrase: String,
+    // (address, PKCS#8 document) of every loaded key
+    keys: Vec<(Address, Vec<u8>)>,
+}
+
+fn encryption_key(passphrase: &str, salt: &[u8], iterations: u32) -> Result<LessSafeKey, WalletError> {
+    let iterations = NonZeroU32::new(iterations).ok_or_else(|| WalletError::Format("zero iterations".to_string()))?;
+    let mut key_bytes = [0u8; 32];
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, salt, passphrase.as_bytes(), &mut key_bytes);
+    Ok(LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key_bytes).unwrap()))
+}
+
+fn decode_hex(field: &str, value: &str) -> Result<Vec<u8>, WalletError> {
+    hex::decode(value).map_err(|e| WalletError::Format(format!("{}: {}", field, e)))
+}
+
+impl Wallet {
+    /// Open the keystore in `dir`, creating the directory if needed, and decrypt every `*.json` key file in it.
+    pub fn open(dir: &Path, passphrase: &str) -> Result<Self, WalletError> {
+        fs::create_dir_all(dir)?;
+        let mut wallet = Wallet { dir: dir.to_path_buf(), passphrase: passphrase.to_string(), keys: Vec::new() };
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        paths.sort();
+        for path in paths {
+            let (address, pkcs8) = wallet.load_key_file(&path)?;
+            wallet.keys.push((address, pkcs8));
+        }
+        info!("Loaded {} keys from keystore {}", wallet.keys.len(), dir.display());
+        Ok(wallet)
+    }
+
+    fn load_key_file(&self, path: &Path) -> Result<(Address, Vec<u8>), WalletError> {
+        let contents = fs::read_to_string(path)?;
+        let key_file: KeyFile = serde_json::from_str(&contents).map_err(|e| WalletError::Format(e.to_string()))?;
+        let address = key_file
+            .address
+            .parse::<Address>()
+            .map_err(|e| WalletError::Format(format!("address: {}", e)))?;
+        let salt = decode_hex("salt", &key_file.salt)?;
+        let nonce = decode_hex("nonce", &key_file.nonce)?;
+        let mut in_out = decode_hex("ciphertext", &key_file.ciphertext)?;
+
+        let key = encryption_key(&self.passphrase, &salt, key_file.iterations)?;
+        let nonce = Nonce::try_assume_unique_for_key(&nonce).map_err(|_| WalletError::Format("nonce length".to_string()))?;
+        let pkcs8 = key
+            .open_in_place(nonce, Aad::from(address.0), &mut in_out)
+            .map_err(|_| WalletError::Decrypt(path.to_path_buf()))?
+            .to_vec();
+
+        // the stored address must belong to the decrypted key
+        let key_pair = Ed25519KeyPair::from_pkcs8(&pkcs8).map_err(|_| WalletError::Format("invalid PKCS#8 key".to_string()))?;
+        if Address::from_public_key_bytes(key_pair.public_key().as_ref()) != address {
+            return Err(WalletError::Format("address does not match key".to_string()));
+        }
+        Ok((address, pkcs8))
+    }
+
+    /// Generate a new key with the system random number generator and write it encrypted to the keystore.
+    pub fn generate(&mut self) -> Result<Address, WalletError> {
+        let rng = SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let address = Address::from_public_key_bytes(key_pair.public_key().as_ref());
+
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt).unwrap();
+        rng.fill(&mut nonce).unwrap();
+        let key = encryption_key(&self.passphrase, &salt, PBKDF2_ITERATIONS)?;
+        let mut in_out = pkcs8.as_ref().to_vec();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(address.0), &mut in_out)
+            .unwrap();
+
+        let key_file = KeyFile {
+            address: address.to_string(),
+            iterations: PBKDF2_ITERATIONS,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(in_out),
+        };
+        let path = self.dir.join(format!("{}.json", address));
+        fs::write(&path, serde_json::to_string_pretty(&key_file).unwrap())?;
+        self.keys.push((address, pkcs8.as_ref().to_vec()));
+        Ok(address)
+    }
+
+    pub fn addresses(&self) -> Vec<Address> {
+        self.keys.iter().map(|(address, _)| *address).collect()
+    }
+
+    pub fn key_pair(&self, address: &Address) -> Option<Ed25519KeyPair> {
+        self.keys
+            .iter()
+            .find(|(a, _)| a == address)
+            .map(|(_, pkcs8)| Ed25519KeyPair::from_pkcs8(pkcs8).unwrap())
+    }
+
+    pub fn key_pairs(&self) -> Vec<Ed25519KeyPair> {
+        self.keys.iter().map(|(_, pkcs8)| Ed25519KeyPair::from_pkcs8(pkcs8).unwrap()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keystore_round_trip() {
+        let dir = std::env::temp_dir().join(format!("keystore-test-{}", rand::random::<u64>()));
+        let mut wallet = Wallet::open(&dir, "correct horse").unwrap();
+        let address = wallet.generate().unwrap();
+
+        let reopened = Wallet::open(&dir, "correct horse").unwrap();
+        assert_eq!(reopened.addresses(), vec![address]);
+        let key_pair = reopened.key_pair(&address).unwrap();
+        assert_eq!(Address::from_public_key_bytes(key_pair.public_key().as_ref()), address);
+
+        assert!(matches!(Wallet::open(&dir, "wrong horse"), Err(WalletError::Decrypt(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}